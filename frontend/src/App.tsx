@@ -1,23 +1,124 @@
 import "./App.css";
-import KnobControl from "./controls/KnobControl";
+import { useEffect, useState } from "react";
+import { arrange } from "./arrange";
+import Control from "./controls/Control";
+import { Device, ServerMessage } from "./types";
+
+// randomUUID is only there on https and localhost, plain http pages build their own
+function newClientId() {
+  if (typeof crypto.randomUUID == "function") {
+    return crypto.randomUUID();
+  }
+  const bytes = crypto.getRandomValues(new Uint8Array(16));
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+// kept across reloads so host approvals stick to this browser, private windows that
+// refuse storage get a new one per load
+function clientId() {
+  try {
+    const stored = localStorage.getItem("client");
+    if (stored) {
+      return stored;
+    }
+  } catch {
+    // no storage, fall through
+  }
+  const id = newClientId();
+  try {
+    localStorage.setItem("client", id);
+  } catch {
+    // keep it for this load only
+  }
+  return id;
+}
+
+// same host as the page, /ws?name=... passes the name on
+function socketUrl() {
+  const protocol = window.location.protocol == "https:" ? "wss" : "ws";
+  const query = new URLSearchParams({ client: clientId() });
+  const name = new URLSearchParams(window.location.search).get("name");
+  if (name) {
+    query.set("name", name);
+  }
+  return `${protocol}://${window.location.host}/ws?${query}`;
+}
 
 function App() {
-  const config = window.config || {};
+  const [socket, setSocket] = useState<WebSocket | null>(null);
+  const [devices, setDevices] = useState<Device[]>([]);
+  const [page, setPage] = useState(0);
+  // positions from the last Reset, controls are remounted there when one comes in
+  const [resets, setResets] = useState<{ count: number; positions: Map<number, number> }>({
+    count: 0,
+    positions: new Map(),
+  });
+
+  useEffect(() => {
+    const socket = new WebSocket(socketUrl());
+    socket.binaryType = "arraybuffer";
+
+    socket.onopen = () => {
+      console.log("WebSocket connection established");
+    };
+
+    socket.onclose = () => {
+      console.log("WebSocket connection closed");
+      setDevices([]);
+    };
 
-  const socket = new WebSocket("wss://127.0.0.1:3000/ws");
+    socket.onmessage = (e: MessageEvent) => {
+      if (typeof e.data != "string") {
+        return;
+      }
+      const message = JSON.parse(e.data) as ServerMessage;
+      if (typeof message == "object" && "Devices" in message) {
+        setDevices(message.Devices);
+      } else if (typeof message == "object" && "Reset" in message) {
+        setResets((r) => ({ count: r.count + 1, positions: new Map(message.Reset) }));
+      }
+    };
 
-  socket.onopen = () => {
-    console.log("WebSocket connection established");
-  };
+    setSocket(socket);
+    return () => socket.close();
+  }, []);
 
-  socket.onclose = () => {
-    console.log("WebSocket connection closed");
-  };
+  const sections = arrange(devices);
+  const pages = [...new Set(sections.map((s) => s.page))];
 
   return (
     <>
       <h1>jam with me - turn the knobs!</h1>
-      <KnobControl socket={socket} labelText="yeah" cc={config.cc} />
+      {pages.length > 1 && (
+        <nav className="pages">
+          {pages.map((p) => (
+            <button key={p} className={p == page ? "switch on" : "switch"} onClick={() => setPage(p)}>
+              {p + 1}
+            </button>
+          ))}
+        </nav>
+      )}
+      {socket &&
+        sections
+          .filter((s) => s.page == (pages.includes(page) ? page : pages[0]))
+          .map((section) => (
+            <section className="group" key={section.group ?? ""}>
+              {section.group && <h2>{section.group}</h2>}
+              {section.devices.map((device) => (
+                <div
+                  className={`control ${device.layout.size.toLowerCase()}${device.locked ? " locked" : ""}`}
+                  style={{ borderColor: device.layout.color ?? undefined }}
+                  key={`${device.id}-${resets.count}`}
+                >
+                  <Control
+                    socket={socket}
+                    device={device}
+                    initial={resets.positions.get(device.id)}
+                  />
+                </div>
+              ))}
+            </section>
+          ))}
     </>
   );
 }
@@ -0,0 +1,26 @@
+import { Device } from "./types";
+
+export interface Section {
+  page: number;
+  group: string | null;
+  devices: Device[];
+}
+
+// same order as util::arrange: by page, then by group in the order groups first
+// show up, then by order, ties keep the list order
+export function arrange(devices: Device[]): Section[] {
+  const sections: Section[] = [];
+  for (const device of devices) {
+    const { page, group } = device.layout;
+    let section = sections.find((s) => s.page == page && s.group == group);
+    if (!section) {
+      section = { page, group, devices: [] };
+      sections.push(section);
+    }
+    section.devices.push(device);
+  }
+  // Array.prototype.sort is stable
+  sections.sort((a, b) => a.page - b.page);
+  sections.forEach((s) => s.devices.sort((a, b) => a.layout.order - b.layout.order));
+  return sections;
+}
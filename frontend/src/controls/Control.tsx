@@ -0,0 +1,64 @@
+import React from "react";
+import { Device } from "../types";
+import KeysControl from "./KeysControl";
+import KnobControl from "./KnobControl";
+import SelectorControl from "./SelectorControl";
+import SlideControl from "./SlideControl";
+import SwitchControl from "./SwitchControl";
+import XYControl from "./XYControl";
+
+type ControlProps = {
+  socket: WebSocket;
+  device: Device;
+  // where the control starts after a panic, see ServerMessage::Reset
+  initial?: number;
+};
+
+// picks the control for a device's ui type
+const Control: React.FC<ControlProps> = ({ socket, device, initial }) => {
+  const props = {
+    socket,
+    labelText: device.description,
+    id: device.id,
+    // locked devices are shown, but the server drops what they send
+    disabled: device.locked,
+  };
+  const positioned = { ...props, initial };
+  const params = device.params;
+
+  switch (device.ui_type) {
+    case "Knob":
+      return <KnobControl {...positioned} />;
+    case "Slide":
+      return <SlideControl {...positioned} />;
+    case "Check":
+      return <SwitchControl {...positioned} latch={true} />;
+    case "Button":
+      return <SwitchControl {...positioned} latch={false} />;
+    case "Toggle":
+      return (
+        <SwitchControl
+          {...positioned}
+          latch={typeof params == "object" && "Toggle" in params ? params.Toggle.latch : true}
+        />
+      );
+    case "XY":
+      return <XYControl {...positioned} />;
+    case "Selector":
+      return (
+        <SelectorControl
+          {...positioned}
+          steps={typeof params == "object" && "Selector" in params ? params.Selector.steps : []}
+        />
+      );
+    case "Keys":
+      return (
+        <KeysControl
+          {...props}
+          count={typeof params == "object" && "Keys" in params ? params.Keys.count : 0}
+        />
+      );
+  }
+};
+
+export default Control;
@@ -0,0 +1,37 @@
+import React from "react";
+import { sendValues } from "./send";
+
+type KeysControlProps = {
+  socket: WebSocket;
+  labelText: string;
+  id: number;
+  disabled: boolean;
+  count: number;
+};
+
+const VELOCITY = 100;
+
+// [key, velocity], velocity 0 releases the key
+const KeysControl: React.FC<KeysControlProps> = ({ socket, labelText, id, disabled, count }) => {
+  const press = (key: number, velocity: number) => sendValues(socket, id, [key, velocity]);
+
+  return (
+    <>
+      <div>{labelText}</div>
+      <div className="keys">
+        {Array.from({ length: count }, (_, key) => (
+          <button
+            key={key}
+            className="key"
+            disabled={disabled}
+            onPointerDown={() => press(key, VELOCITY)}
+            onPointerUp={() => press(key, 0)}
+            onPointerLeave={(e) => e.buttons != 0 && press(key, 0)}
+          />
+        ))}
+      </div>
+    </>
+  );
+};
+
+export default KeysControl;
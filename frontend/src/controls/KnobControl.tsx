@@ -1,31 +1,30 @@
 import React, { useState } from "react";
 import { Knob, KnobChangeEvent } from "primereact/knob";
+import { sendValues } from "./send";
 
 type KnobControlProps = {
   socket: WebSocket;
   labelText: string;
-  cc: number;
+  id: number;
+  disabled: boolean;
+  initial?: number;
 };
 
-const KnobControl: React.FC<KnobControlProps> = ({ socket, labelText, cc }) => {
-  const [value, setValue] = useState(0);
+const KnobControl: React.FC<KnobControlProps> = ({ socket, labelText, id, disabled, initial }) => {
+  const [value, setValue] = useState(initial ?? 0);
 
   const handleChange = (e: KnobChangeEvent) => {
     if (e.value == value) {
       return;
     }
     setValue(e.value);
-    const buffer = new ArrayBuffer(2);
-    const view = new DataView(buffer);
-    view.setInt8(0, cc);
-    view.setInt8(1, e.value);
-    socket.send(buffer);
+    sendValues(socket, id, [e.value]);
   };
 
   return (
     <>
       <div>{labelText}</div>
-      <Knob value={value} onChange={handleChange} min={0} max={127} />
+      <Knob value={value} onChange={handleChange} min={0} max={127} disabled={disabled} />
     </>
   );
 };
@@ -0,0 +1,42 @@
+import React, { useState } from "react";
+import { Step } from "../types";
+import { sendValues } from "./send";
+
+type SelectorControlProps = {
+  socket: WebSocket;
+  labelText: string;
+  id: number;
+  disabled: boolean;
+  initial?: number;
+  steps: Step[];
+};
+
+// sends the index of the step, the server looks up its value
+const SelectorControl: React.FC<SelectorControlProps> = ({ socket, labelText, id, disabled, initial, steps }) => {
+  const [selected, setSelected] = useState<number | null>(initial ?? null);
+
+  const select = (index: number) => {
+    setSelected(index);
+    sendValues(socket, id, [index]);
+  };
+
+  return (
+    <>
+      <div>{labelText}</div>
+      <div className="selector">
+        {steps.map((step, index) => (
+          <button
+            key={index}
+            className={index == selected ? "switch on" : "switch"}
+            disabled={disabled}
+            onClick={() => select(index)}
+          >
+            {step.label}
+          </button>
+        ))}
+      </div>
+    </>
+  );
+};
+
+export default SelectorControl;
@@ -0,0 +1,39 @@
+import React, { useState } from "react";
+import { sendValues } from "./send";
+
+type SlideControlProps = {
+  socket: WebSocket;
+  labelText: string;
+  id: number;
+  disabled: boolean;
+  initial?: number;
+};
+
+const SlideControl: React.FC<SlideControlProps> = ({ socket, labelText, id, disabled, initial }) => {
+  const [value, setValue] = useState(initial ?? 0);
+
+  const handleChange = (e: React.ChangeEvent<HTMLInputElement>) => {
+    const next = Number(e.target.value);
+    if (next == value) {
+      return;
+    }
+    setValue(next);
+    sendValues(socket, id, [next]);
+  };
+
+  return (
+    <>
+      <div>{labelText}</div>
+      <input
+        type="range"
+        min={0}
+        max={127}
+        value={value}
+        onChange={handleChange}
+        disabled={disabled}
+      />
+    </>
+  );
+};
+
+export default SlideControl;
@@ -0,0 +1,38 @@
+import React, { useState } from "react";
+import { sendValues } from "./send";
+
+type SwitchControlProps = {
+  socket: WebSocket;
+  labelText: string;
+  id: number;
+  disabled: boolean;
+  initial?: number;
+  // latching switches stay where they were put, others only stay on while held
+  latch: boolean;
+};
+
+// checks, buttons and toggles, the server maps 127/0 onto the device's on/off values
+const SwitchControl: React.FC<SwitchControlProps> = ({ socket, labelText, id, disabled, initial, latch }) => {
+  const [on, setOn] = useState((initial ?? 0) >= 64);
+
+  const set = (next: boolean) => {
+    setOn(next);
+    sendValues(socket, id, [next ? 127 : 0]);
+  };
+
+  const handlers = latch
+    ? { onClick: () => set(!on) }
+    : {
+        onPointerDown: () => set(true),
+        onPointerUp: () => set(false),
+        onPointerLeave: () => on && set(false),
+      };
+
+  return (
+    <button className={on ? "switch on" : "switch"} disabled={disabled} {...handlers}>
+      {labelText}
+    </button>
+  );
+};
+
+export default SwitchControl;
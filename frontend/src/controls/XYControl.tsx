@@ -0,0 +1,55 @@
+import React, { useState } from "react";
+import { sendValues } from "./send";
+
+type XYControlProps = {
+  socket: WebSocket;
+  labelText: string;
+  id: number;
+  disabled: boolean;
+  initial?: number;
+};
+
+const SIZE = 160;
+
+// x and y in one frame, y grows upwards
+const XYControl: React.FC<XYControlProps> = ({ socket, labelText, id, disabled, initial }) => {
+  const [position, setPosition] = useState<[number, number]>([initial ?? 0, initial ?? 0]);
+
+  const move = (e: React.PointerEvent<HTMLDivElement>) => {
+    if (disabled || e.buttons == 0) {
+      return;
+    }
+    const rect = e.currentTarget.getBoundingClientRect();
+    const scale = (offset: number, size: number) =>
+      Math.round(Math.min(Math.max(offset / size, 0), 1) * 127);
+    const x = scale(e.clientX - rect.left, rect.width);
+    const y = 127 - scale(e.clientY - rect.top, rect.height);
+    if (x == position[0] && y == position[1]) {
+      return;
+    }
+    setPosition([x, y]);
+    sendValues(socket, id, [x, y]);
+  };
+
+  return (
+    <>
+      <div>{labelText}</div>
+      <div
+        className={disabled ? "xy disabled" : "xy"}
+        style={{ width: SIZE, height: SIZE }}
+        onPointerDown={move}
+        onPointerMove={move}
+      >
+        <div
+          className="xy-dot"
+          style={{
+            left: (position[0] / 127) * SIZE,
+            top: ((127 - position[1]) / 127) * SIZE,
+          }}
+        />
+      </div>
+    </>
+  );
+};
+
+export default XYControl;
@@ -0,0 +1,11 @@
+// [id_hi, id_lo, values...], see util::UserFrame
+export function sendValues(socket: WebSocket, id: number, values: number[]) {
+  if (socket.readyState !== WebSocket.OPEN) {
+    return;
+  }
+  const buffer = new ArrayBuffer(2 + values.length);
+  const view = new DataView(buffer);
+  view.setUint16(0, id);
+  values.forEach((value, i) => view.setUint8(2 + i, value));
+  socket.send(buffer);
+}
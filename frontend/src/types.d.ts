@@ -1,9 +1,58 @@
 export {};
 
+export interface Layout {
+  page: number;
+  group: string | null;
+  order: number;
+  color: string | null;
+  size: "Small" | "Medium" | "Large";
+  icon: string | null;
+}
+
+export type UIType =
+  | "Slide"
+  | "Check"
+  | "Knob"
+  | "Button"
+  | "Toggle"
+  | "XY"
+  | "Selector"
+  | "Keys";
+
+export interface Step {
+  label: string;
+  value: number;
+}
+
+// serde's externally tagged util::UIParams
+export type UIParams =
+  | "None"
+  | { Button: { on: number; off: number } }
+  | { Toggle: { on: number; off: number; latch: boolean } }
+  | { XY: { cc_y: number } }
+  | { Selector: { steps: Step[] } }
+  | { Keys: { base_note: number; count: number } };
+
+// util::Device, only what users need
+export interface Device {
+  id: number;
+  ui_type: UIType;
+  description: string;
+  params: UIParams;
+  layout: Layout;
+  locked: boolean;
+}
+
+// util::ServerMessage as far as users get it
+export type ServerMessage =
+  | { Devices: Device[] }
+  | { Reset: [number, number][] }
+  | "ShuttingDown";
+
 declare global {
   interface Window {
     config: {
-      cc: number;
+      id: number;
     };
   }
 }
@@ -0,0 +1,149 @@
+use flume::{Receiver, Sender};
+use slint::{ComponentHandle, Model, ModelRc, SharedString, VecModel};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::SystemTime;
+use util::{ClientId, DeviceCmd, DeviceId, HostFrame, MidiKind, UserId};
+
+use crate::{AppState, AppWindow, Meter, Request};
+
+const LOG_LEN: usize = 100;
+// requests waiting for the approve button, older ones drop off
+const REQUESTS_LEN: usize = 3;
+
+pub enum Activity {
+    Value(HostFrame, SystemTime),
+    Joined(UserId, String),
+    Left(UserId),
+    // a user wants in on a host approved device
+    Requested(UserId, ClientId, DeviceId),
+}
+
+// device descriptions by id, kept up to date by whoever shows the exposed list
+pub type DeviceNames = Rc<RefCell<HashMap<DeviceId, String>>>;
+
+// local time, the same as the host's clock shows
+fn clock(at: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(at)
+        .format("%H:%M:%S")
+        .to_string()
+}
+
+// newest line on top, one meter per control output that has sent something
+pub fn activity_monitor(
+    app: AppWindow,
+    activity_rx: Receiver<Activity>,
+    names: DeviceNames,
+    device_tx: Sender<DeviceCmd>,
+) {
+    let log = Rc::new(VecModel::<SharedString>::default());
+    let meters = Rc::new(VecModel::<Meter>::default());
+    let requests = Rc::new(VecModel::<Request>::default());
+    let app_state = app.global::<AppState>();
+    app_state.set_activity(ModelRc::from(log.clone()));
+    app_state.set_meters(ModelRc::from(meters.clone()));
+    app_state.set_requests(ModelRc::from(requests.clone()));
+
+    let pending = requests.clone();
+    app_state.on_approve(move |device, client| {
+        let _ = device_tx.send(DeviceCmd::Approve(device as DeviceId, client.to_string()));
+        if let Some(i) = pending
+            .iter()
+            .position(|r| r.device == device && r.client == client)
+        {
+            pending.remove(i);
+        }
+    });
+
+    let _ = slint::spawn_local(async move {
+        let mut users: HashMap<UserId, String> = HashMap::new();
+        let mut meter_keys: Vec<(DeviceId, MidiKind, u8)> = vec![];
+        let push = |line: String| {
+            log.insert(0, SharedString::from(line));
+            if log.row_count() > LOG_LEN {
+                log.remove(LOG_LEN);
+            }
+        };
+
+        while let Ok(activity) = activity_rx.recv_async().await {
+            match activity {
+                Activity::Joined(user, name) => {
+                    push(format!("{} {name} joined", clock(SystemTime::now())));
+                    users.insert(user, name);
+                }
+                Activity::Left(user) => {
+                    let name = users
+                        .remove(&user)
+                        .unwrap_or_else(|| format!("user {user}"));
+                    push(format!("{} {name} left", clock(SystemTime::now())));
+                }
+                Activity::Requested(user, client, device) => {
+                    let name = users
+                        .get(&user)
+                        .cloned()
+                        .unwrap_or_else(|| format!("user {user}"));
+                    let control = names
+                        .borrow()
+                        .get(&device)
+                        .cloned()
+                        .unwrap_or_else(|| format!("#{device}"));
+                    push(format!(
+                        "{} {name} (client {client}) asks for {control} (id {device})",
+                        clock(SystemTime::now())
+                    ));
+                    let request = Request {
+                        text: SharedString::from(format!("{name} asks for {control}")),
+                        device: device as i32,
+                        client: SharedString::from(client),
+                    };
+                    if !requests
+                        .iter()
+                        .any(|r| r.device == request.device && r.client == request.client)
+                    {
+                        requests.insert(0, request);
+                        if requests.row_count() > REQUESTS_LEN {
+                            requests.remove(REQUESTS_LEN);
+                        }
+                    }
+                }
+                Activity::Value(frame, at) => {
+                    let names = names.borrow();
+                    let user = users
+                        .get(&frame.user)
+                        .cloned()
+                        .unwrap_or_else(|| format!("user {}", frame.user));
+                    let control = names
+                        .get(&frame.device)
+                        .cloned()
+                        .unwrap_or_else(|| format!("#{}", frame.device));
+                    let label = match frame.kind {
+                        MidiKind::Cc => format!("{control} cc{}", frame.number),
+                        MidiKind::Note => format!("{control} n{}", frame.number),
+                    };
+                    push(format!("{} {user} {label} {}", clock(at), frame.value));
+
+                    // meters of hidden devices go away with the next value
+                    for i in (0..meter_keys.len()).rev() {
+                        if !names.contains_key(&meter_keys[i].0) {
+                            meter_keys.remove(i);
+                            meters.remove(i);
+                        }
+                    }
+                    let meter = Meter {
+                        label: SharedString::from(label),
+                        value: frame.value as i32,
+                    };
+                    let key = (frame.device, frame.kind, frame.number);
+                    match meter_keys.iter().position(|k| *k == key) {
+                        Some(i) => meters.set_row_data(i, meter),
+                        None => {
+                            meter_keys.push(key);
+                            meters.push(meter);
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
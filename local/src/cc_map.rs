@@ -0,0 +1,160 @@
+use serde::Deserialize;
+use std::path::Path;
+use util::{Device, DeviceParseError, UIType};
+
+use crate::layout_file::{line_of, LayoutFileError};
+
+// one row of a community cc map, e.g. github.com/pencilresearch/midi
+// columns that aren't listed here are ignored
+#[derive(Deserialize)]
+struct CcMapRow {
+    manufacturer: String,
+    device: String,
+    #[serde(default)]
+    section: String,
+    parameter_name: String,
+    #[serde(default)]
+    cc_msb: String,
+    #[serde(default)]
+    cc_min_value: String,
+    #[serde(default)]
+    cc_max_value: String,
+}
+
+impl CcMapRow {
+    // an empty filter takes everything, otherwise it has to show up in "manufacturer device"
+    fn matches(&self, instrument: &str) -> bool {
+        let instrument = instrument.trim().to_lowercase();
+        instrument.is_empty()
+            || format!("{} {}", self.manufacturer, self.device)
+                .to_lowercase()
+                .contains(&instrument)
+    }
+
+    fn into_device(self) -> Result<Device, DeviceParseError> {
+        let mut device =
+            Device::from_string_args(self.cc_msb, UIType::Knob.to_string(), self.parameter_name)?;
+        let value = |v: &str, or: u8| v.trim().parse::<u32>().map_or(or, |v| v.min(127) as u8);
+        device.min = value(&self.cc_min_value, 0);
+        device.max = value(&self.cc_max_value, 127);
+        // maps have no default, so it sits at the bottom of the range
+        device.default = device.default.max(device.min).min(device.max);
+        device.layout.group = Some(self.section).filter(|s| !s.trim().is_empty());
+        device.validate()?;
+        Ok(device)
+    }
+}
+
+pub struct CcMapImport {
+    pub devices: Vec<Device>,
+    pub rejected: Vec<String>,
+    // nrpn only parameters have nothing a device could send
+    pub without_cc: usize,
+}
+
+pub fn import(path: &Path, instrument: &str) -> Result<CcMapImport, LayoutFileError> {
+    parse(&std::fs::read_to_string(path)?, instrument)
+}
+
+fn parse(content: &str, instrument: &str) -> Result<CcMapImport, LayoutFileError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let headers = rdr.headers()?.clone();
+
+    let mut import = CcMapImport {
+        devices: vec![],
+        rejected: vec![],
+        without_cc: 0,
+    };
+    for record in rdr.records() {
+        let record = record?;
+        let line = line_of(content, &record);
+        let row: CcMapRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                import.rejected.push(format!("line {line} {e}"));
+                continue;
+            }
+        };
+        if !row.matches(instrument) {
+            continue;
+        }
+        if row.cc_msb.trim().is_empty() {
+            import.without_cc += 1;
+            continue;
+        }
+        match row.into_device() {
+            Ok(device) => import.devices.push(device),
+            Err(e) => import.rejected.push(e.at(line).to_string()),
+        }
+    }
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str =
+        "manufacturer,device,section,parameter_name,cc_msb,cc_min_value,cc_max_value\n\
+        Acme,Synth One,Filter,Cutoff,74,0,127\n\
+        Acme,Synth One,Filter,Resonance,71,20,100\n\
+        Acme,Synth One,,Mod Wheel,1,,\n\
+        Acme,Synth One,Env,Attack,,0,127\n\
+        Other,Drum Box,,Volume,7,0,127\n";
+
+    #[test]
+    fn rows_are_filtered_by_instrument() {
+        let import = parse(MAP, "synth one").unwrap();
+        let names = import
+            .devices
+            .iter()
+            .map(|d| d.description.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Cutoff", "Resonance", "Mod Wheel"]);
+        assert_eq!(import.without_cc, 1);
+        assert!(import.rejected.is_empty());
+        assert_eq!(parse(MAP, "").unwrap().devices.len(), 4);
+    }
+
+    #[test]
+    fn ranges_come_from_the_map_and_the_default_stays_inside() {
+        let import = parse(MAP, "synth").unwrap();
+        let resonance = &import.devices[1];
+        assert_eq!((resonance.cc, resonance.min, resonance.max), (71, 20, 100));
+        assert_eq!(resonance.default, 20);
+        assert_eq!(resonance.layout.group.as_deref(), Some("Filter"));
+        // blank columns leave the full range and no group
+        let wheel = &import.devices[2];
+        assert_eq!((wheel.min, wheel.max, wheel.default), (0, 127, 0));
+        assert_eq!(wheel.layout.group, None);
+    }
+
+    #[test]
+    fn bad_rows_are_rejected_by_line() {
+        let map = "manufacturer,device,parameter_name,cc_msb,cc_min_value,cc_max_value\n\
+            Acme,Synth,Cutoff,74,0,127\n\
+            Acme,Synth,Broken,200,0,127\n\
+            Acme,Synth,Upside Down,10,100,20\n\
+            Acme,Synth\n";
+        let import = parse(map, "").unwrap();
+        assert_eq!(import.devices.len(), 1);
+        assert_eq!(import.rejected.len(), 3);
+        assert!(
+            import.rejected[0].starts_with("line 3 "),
+            "{}",
+            import.rejected[0]
+        );
+        assert!(
+            import.rejected[1].starts_with("line 4 min 100 is above max 20"),
+            "{}",
+            import.rejected[1]
+        );
+        assert!(
+            import.rejected[2].starts_with("line 5 "),
+            "{}",
+            import.rejected[2]
+        );
+    }
+}
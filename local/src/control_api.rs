@@ -0,0 +1,403 @@
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use flume::Sender;
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use util::{
+    client_id, Device, DeviceCmd, DeviceId, DeviceParseError, DevicePatch, DeviceUpdate,
+    LayoutEdit, Midi, MidiCmd,
+};
+
+use crate::layout_file;
+use crate::setters::Status;
+
+// everything the api does goes through the same channels as the ui callbacks
+#[derive(Clone)]
+pub struct ControlApi {
+    pub device_tx: Sender<DeviceCmd>,
+    pub midi_tx: Sender<MidiCmd>,
+    pub status_tx: Sender<Status>,
+    pub passthrough: Arc<Mutex<bool>>,
+    pub midi: Arc<Mutex<Midi>>,
+}
+
+#[derive(Deserialize)]
+struct Port {
+    port: String,
+}
+
+#[derive(Deserialize)]
+struct Passthrough {
+    on: bool,
+}
+
+#[derive(Deserialize)]
+struct Cc {
+    #[serde(default)]
+    channel: u8,
+    cc: u8,
+    value: u8,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    #[serde(default)]
+    channel: u8,
+    note: u8,
+    velocity: u8,
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    device: DeviceId,
+    client: String,
+}
+
+#[derive(Deserialize)]
+struct File {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Preset {
+    name: String,
+}
+
+// what every request has to get past, see guard
+#[derive(Clone)]
+struct Guard {
+    token: Arc<str>,
+    addr: SocketAddr,
+}
+
+// only meant for scripts on the same machine. requests need the token as a bearer token, and a
+// Host header naming this machine so web pages can't reach it through dns rebinding
+pub fn control_api(rt: &Runtime, addr: SocketAddr, token: Option<String>, api: ControlApi) {
+    let Some(token) = token.filter(|t| !t.is_empty()) else {
+        warn!(%addr, "not starting the control api without a token, set MIDISERV_CONTROL_TOKEN");
+        return;
+    };
+    if !addr.ip().is_loopback() {
+        warn!(%addr, "control api is reachable from other machines");
+    }
+    let guard = Guard {
+        token: token.into(),
+        addr,
+    };
+    let app = Router::new()
+        .route("/ports", get(ports))
+        .route("/port", post(port))
+        .route("/passthrough", get(get_passthrough).post(set_passthrough))
+        .route("/cc", post(cc))
+        .route("/note", post(note))
+        .route("/expose", post(expose))
+        .route("/update", post(update))
+        .route("/hide", post(hide))
+        .route("/approve", post(approve))
+        .route("/undo", post(undo))
+        .route("/redo", post(redo))
+        .route("/panic", post(panic))
+        .route("/layout/open", post(open_layout))
+        .route("/layout/save", post(save_layout))
+        .route("/preset/load", post(load_preset))
+        .route("/preset/save", post(save_preset))
+        .layer(middleware::from_fn_with_state(guard, check_request))
+        .with_state(api);
+
+    rt.spawn(async move {
+        match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!(%addr, "control api listening");
+                if let Err(e) = axum::serve(listener, app).await {
+                    warn!("control api stopped: {e}");
+                }
+            }
+            Err(e) => warn!(%addr, "failed to start control api: {e}"),
+        }
+    });
+}
+
+fn refused(status: StatusCode, error: impl ToString) -> Response {
+    (
+        status,
+        Json(json!({
+            "success": false,
+            "error": error.to_string(),
+        })),
+    )
+        .into_response()
+}
+
+async fn check_request(State(guard): State<Guard>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if !local_host(host, guard.addr) {
+        warn!(host, "control api request for another host");
+        return refused(StatusCode::FORBIDDEN, "Host not allowed");
+    }
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    if !token.is_some_and(|t| same(t.as_bytes(), guard.token.as_bytes())) {
+        return refused(StatusCode::UNAUTHORIZED, "Missing or wrong token");
+    }
+    next.run(request).await
+}
+
+// localhost or the address the api was bound to, with or without the port
+fn local_host(host: &str, addr: SocketAddr) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip == addr.ip())
+}
+
+// takes as long for every wrong token of the same length
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn unprocessable(e: DeviceParseError) -> Response {
+    refused(StatusCode::UNPROCESSABLE_ENTITY, e)
+}
+
+fn midi_bytes(channel: u8, values: [(&'static str, u8); 2]) -> Result<(), DeviceParseError> {
+    if channel > 15 {
+        return Err(DeviceParseError::ChannelOutOfRange(channel));
+    }
+    values
+        .into_iter()
+        .try_for_each(|(field, value)| match value {
+            0..=127 => Ok(()),
+            _ => Err(DeviceParseError::ValueOutOfRange(field, value)),
+        })
+}
+
+// file names under the layout dir, nothing that climbs out of it
+fn layout_path(path: &Path) -> Option<PathBuf> {
+    let inside = path.components().all(|c| matches!(c, Component::Normal(_)));
+    (inside && path.components().next().is_some()).then(|| layout_file::layout_dir().join(path))
+}
+
+fn accepted<T>(sent: Result<(), flume::SendError<T>>) -> (StatusCode, Json<serde_json::Value>) {
+    match sent {
+        Ok(()) => (StatusCode::ACCEPTED, Json(json!({ "success": true }))),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "success": false,
+                "error": "Shutting down",
+            })),
+        ),
+    }
+}
+
+async fn device(api: &ControlApi, cmd: DeviceCmd) -> impl IntoResponse {
+    accepted(api.device_tx.send_async(cmd).await)
+}
+
+async fn midi(api: &ControlApi, cmd: MidiCmd) -> impl IntoResponse {
+    accepted(api.midi_tx.send_async(cmd).await)
+}
+
+async fn ports(State(api): State<ControlApi>) -> impl IntoResponse {
+    Json(json!(api.midi.lock().await.get_ports()))
+}
+
+async fn port(State(api): State<ControlApi>, Json(port): Json<Port>) -> impl IntoResponse {
+    let index = api.midi.lock().await.find_port(&port.port);
+    match index {
+        Some(i) => midi(&api, MidiCmd::Port(i)).await.into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "error": "No such midi port",
+            })),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_passthrough(State(api): State<ControlApi>) -> impl IntoResponse {
+    Json(json!({ "on": *api.passthrough.lock().await }))
+}
+
+async fn set_passthrough(
+    State(api): State<ControlApi>,
+    Json(passthrough): Json<Passthrough>,
+) -> impl IntoResponse {
+    *api.passthrough.lock().await = passthrough.on;
+    info!(
+        passthrough = passthrough.on,
+        "passthrough set over control api"
+    );
+    accepted(
+        api.status_tx
+            .send_async(Status::Passthrough(passthrough.on))
+            .await,
+    )
+}
+
+async fn cc(State(api): State<ControlApi>, Json(cc): Json<Cc>) -> Response {
+    if let Err(e) = midi_bytes(cc.channel, [("cc", cc.cc), ("value", cc.value)]) {
+        return unprocessable(e);
+    }
+    midi(&api, MidiCmd::Signal(cc.channel, cc.cc, cc.value))
+        .await
+        .into_response()
+}
+
+async fn note(State(api): State<ControlApi>, Json(note): Json<Note>) -> Response {
+    if let Err(e) = midi_bytes(
+        note.channel,
+        [("note", note.note), ("velocity", note.velocity)],
+    ) {
+        return unprocessable(e);
+    }
+    midi(&api, MidiCmd::Note(note.channel, note.note, note.velocity))
+        .await
+        .into_response()
+}
+
+// the same checks as the expose form, the device task checks edits again on the device they hit
+async fn expose(State(api): State<ControlApi>, Json(d): Json<Device>) -> Response {
+    if let Err(e) = d.validate() {
+        return unprocessable(e);
+    }
+    let (edit, layout) = (DevicePatch::replacing(&d), LayoutEdit::replacing(&d.layout));
+    device(&api, DeviceCmd::Expose(d, edit, layout))
+        .await
+        .into_response()
+}
+
+async fn update(State(api): State<ControlApi>, Json(update): Json<DeviceUpdate>) -> Response {
+    if let Err(e) = update.validate() {
+        return unprocessable(e);
+    }
+    device(&api, DeviceCmd::Update(update))
+        .await
+        .into_response()
+}
+
+// positions in the exposed list, like the ui's hide buttons
+async fn hide(State(api): State<ControlApi>, Json(indexes): Json<Vec<usize>>) -> impl IntoResponse {
+    device(&api, DeviceCmd::Hide(indexes)).await
+}
+
+async fn approve(State(api): State<ControlApi>, Json(a): Json<Approval>) -> Response {
+    let Some(client) = client_id(&a.client) else {
+        return refused(StatusCode::UNPROCESSABLE_ENTITY, "invalid client id");
+    };
+    device(&api, DeviceCmd::Approve(a.device, client))
+        .await
+        .into_response()
+}
+
+async fn undo(State(api): State<ControlApi>) -> impl IntoResponse {
+    device(&api, DeviceCmd::Undo).await
+}
+
+async fn redo(State(api): State<ControlApi>) -> impl IntoResponse {
+    device(&api, DeviceCmd::Redo).await
+}
+
+async fn panic(State(api): State<ControlApi>) -> impl IntoResponse {
+    device(&api, DeviceCmd::Panic).await
+}
+
+async fn open_layout(State(api): State<ControlApi>, Json(f): Json<File>) -> Response {
+    match layout_path(&f.path) {
+        Some(path) => device(&api, DeviceCmd::Open(path)).await.into_response(),
+        None => refused(
+            StatusCode::FORBIDDEN,
+            "Layouts have to be in the layout dir",
+        ),
+    }
+}
+
+async fn save_layout(State(api): State<ControlApi>, Json(f): Json<File>) -> Response {
+    let Some(path) = layout_path(&f.path) else {
+        return refused(
+            StatusCode::FORBIDDEN,
+            "Layouts have to be in the layout dir",
+        );
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            return refused(StatusCode::INTERNAL_SERVER_ERROR, e);
+        }
+    }
+    device(&api, DeviceCmd::Save(path)).await.into_response()
+}
+
+async fn load_preset(State(api): State<ControlApi>, Json(p): Json<Preset>) -> impl IntoResponse {
+    device(&api, DeviceCmd::LoadPreset(p.name)).await
+}
+
+async fn save_preset(State(api): State<ControlApi>, Json(p): Json<Preset>) -> impl IntoResponse {
+    device(&api, DeviceCmd::SavePreset(p.name)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_hosts_pass() {
+        let addr: SocketAddr = "127.0.0.1:3100".parse().unwrap();
+        for host in [
+            "localhost:3100",
+            "LOCALHOST",
+            "127.0.0.1:3100",
+            "[::1]:3100",
+        ] {
+            assert!(local_host(host, addr), "{host}");
+        }
+        for host in [
+            "evil.example:3100",
+            "localhost.evil.example",
+            "10.0.0.2:3100",
+            "",
+        ] {
+            assert!(!local_host(host, addr), "{host}");
+        }
+        let lan: SocketAddr = "10.0.0.2:3100".parse().unwrap();
+        assert!(local_host("10.0.0.2:3100", lan));
+    }
+
+    #[test]
+    fn layout_paths_stay_in_the_layout_dir() {
+        assert_eq!(
+            layout_path(Path::new("live/set.toml")),
+            Some(layout_file::layout_dir().join("live/set.toml"))
+        );
+        for path in [
+            "../x.toml",
+            "/tmp/x.toml",
+            "live/../../x.toml",
+            "./x.toml",
+            "",
+        ] {
+            assert_eq!(layout_path(Path::new(path)), None, "{path}");
+        }
+    }
+}
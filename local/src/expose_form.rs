@@ -0,0 +1,118 @@
+use util::{Conflict, Curve, Device, DevicePatch, LayoutEdit, Size, UIParams};
+
+use crate::ExposeFields;
+
+// a fresh device from the form, and the patch that edits one already exposed on the
+// same channel and cc. blank fields keep the defaults there and leave edits alone
+pub fn parse(fields: &ExposeFields) -> Result<(Device, DevicePatch, LayoutEdit), String> {
+    // channels are 1-16 in the ui
+    let channel = match fields.channel.trim() {
+        "" => 0,
+        c => c
+            .parse::<u8>()
+            .ok()
+            .filter(|c| (1..=16).contains(c))
+            .map(|c| c - 1)
+            .ok_or(format!("channel '{c}' is not 1-16"))?,
+    };
+    let value = |name: &str, text: &str| match text.trim() {
+        "" => Ok(None),
+        v => v
+            .parse::<u8>()
+            .ok()
+            .filter(|v| *v <= 127)
+            .map(Some)
+            .ok_or(format!("{name} '{v}' is not 0-127")),
+    };
+    let min = value("min", &fields.min)?;
+    let max = value("max", &fields.max)?;
+    let default = value("default", &fields.default)?;
+    let curve = match fields.curve.trim() {
+        "" => None,
+        c => Some(c.parse::<Curve>()?),
+    };
+    let invert = match fields.invert.trim() {
+        "" => None,
+        i => Some(i == "inverted"),
+    };
+    // picking approved starts over with nobody approved, the approve button adds clients
+    let conflict = match fields.conflict.trim() {
+        "" => None,
+        c => Some(c.parse::<Conflict>()?),
+    };
+    let slew = match fields.slew.trim() {
+        "" => None,
+        s => Some(
+            s.parse::<u16>()
+                .map_err(|_| format!("slew '{s}' is not 0-{}", u16::MAX))?,
+        ),
+    };
+    let description = fields.description.trim();
+
+    let mut device = Device::from_string_args(
+        fields.cc.to_string(),
+        fields.ui_type.to_string(),
+        description.to_string(),
+    )
+    .map_err(|e| e.to_string())?;
+    device.channel = channel;
+    device.min = min.unwrap_or(device.min);
+    device.max = max.unwrap_or(device.max);
+    device.default = default.unwrap_or(device.default);
+    device.curve = curve.unwrap_or(device.curve);
+    device.invert = invert.unwrap_or(device.invert);
+    device.conflict = conflict.clone().unwrap_or(device.conflict);
+    device.slew = slew.unwrap_or(device.slew);
+    let params = match fields.params.trim() {
+        "" => None,
+        p => Some(UIParams::parse(device.ui_type, device.cc, p).map_err(|e| e.to_string())?),
+    };
+    device.params = params.clone().unwrap_or(device.params);
+
+    let text = |text: &str| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    // pages are 1 based in the ui, like channels
+    let page = match fields.page.trim() {
+        "" => None,
+        p => Some(
+            p.parse::<u8>()
+                .ok()
+                .filter(|p| *p >= 1)
+                .map(|p| p - 1)
+                .ok_or(format!("page '{p}' is not 1-255"))?,
+        ),
+    };
+    let order = match fields.order.trim() {
+        "" => None,
+        o => Some(
+            o.parse::<i16>()
+                .map_err(|_| format!("order '{o}' is not a whole number"))?,
+        ),
+    };
+    let size = match fields.size.trim() {
+        "" => None,
+        s => Some(s.parse::<Size>()?),
+    };
+    let layout = LayoutEdit {
+        page,
+        group: text(&fields.group),
+        order,
+        color: text(&fields.color),
+        size,
+        icon: text(&fields.icon),
+    };
+
+    let edit = DevicePatch {
+        ui_type: Some(device.ui_type),
+        description: Some(description.to_string()).filter(|d| !d.is_empty()),
+        min,
+        max,
+        default,
+        curve,
+        invert,
+        conflict,
+        slew,
+        params,
+        ..Default::default()
+    };
+    Ok((device, edit, layout))
+}
@@ -1,10 +1,25 @@
 use flume::Sender;
 use reqwest::Client;
-use util::{copy_to_clipboard, Device, DeviceUpdate, Login};
+use std::path::Path;
+use tracing::{debug, info, warn};
+use util::{
+    arrange, copy_to_clipboard, Device, DeviceParseError, DevicePatch, DeviceUpdate, LayoutEdit,
+    Login, MidiCmd, MidiKind,
+};
+
+use crate::cc_map;
+use crate::layout_file;
+use crate::presets;
+use crate::setters::Status;
+
+// how many steps undo can go back
+const HISTORY_LEN: usize = 50;
 
 pub struct ExposedState {
     pub devices: Vec<Device>,
     pub login: Option<Login>,
+    undo: Vec<Vec<Device>>,
+    redo: Vec<Vec<Device>>,
 }
 
 impl ExposedState {
@@ -12,72 +27,739 @@ impl ExposedState {
         ExposedState {
             devices: vec![],
             login: None,
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+
+    // any new edit makes the redone steps meaningless
+    fn record(&mut self, before: Vec<Device>) {
+        if before == self.devices {
+            return;
+        }
+        self.undo.push(before);
+        if self.undo.len() > HISTORY_LEN {
+            self.undo.remove(0);
         }
+        self.redo.clear();
+    }
+
+    // devices and their history belong to the server and session they were synced with,
+    // so the next reconcile adopts what another session kept instead of overwriting it
+    pub fn log_in(&mut self, login: Login, slint_device_tx: Sender<Vec<Device>>) {
+        let same = self
+            .login
+            .as_ref()
+            .is_some_and(|l| l.url == login.url && l.session == login.session);
+        if !same {
+            self.devices.clear();
+            self.undo.clear();
+            self.redo.clear();
+            let _ = slint_device_tx.send(vec![]);
+        }
+        self.login = Some(login);
+    }
+
+    // not an edit, so it isn't undoable either. the server still has the devices and
+    // hands them back when logging in again
+    pub fn forget(&mut self, slint_device_tx: Sender<Vec<Device>>) {
+        self.devices.clear();
+        let _ = slint_device_tx.send(vec![]);
     }
+
+    pub async fn undo(&mut self, slint_device_tx: Sender<Vec<Device>>, status_tx: Sender<Status>) {
+        let Some(previous) = self.undo.pop() else {
+            let _ = status_tx
+                .send_async(Status::Report("nothing to undo".to_string()))
+                .await;
+            return;
+        };
+        let current = self.devices.clone();
+        if self
+            .sync(DeviceUpdate::Replace(previous.clone()), slint_device_tx)
+            .await
+        {
+            info!(steps = self.undo.len(), "undone");
+            self.redo.push(current);
+        } else {
+            self.undo.push(previous);
+        }
+    }
+
+    pub async fn redo(&mut self, slint_device_tx: Sender<Vec<Device>>, status_tx: Sender<Status>) {
+        let Some(next) = self.redo.pop() else {
+            let _ = status_tx
+                .send_async(Status::Report("nothing to redo".to_string()))
+                .await;
+            return;
+        };
+        let current = self.devices.clone();
+        if self
+            .sync(DeviceUpdate::Replace(next.clone()), slint_device_tx)
+            .await
+        {
+            info!(steps = self.redo.len(), "redone");
+            self.undo.push(current);
+        } else {
+            self.redo.push(next);
+        }
+    }
+
     pub fn copy_to_clipboard(&self) {
-        copy_to_clipboard(
-            self.devices
-                .iter()
-                .map(|d| format!("{},{},{}", d.cc, d.ui_type, d.description))
-                .fold(String::new(), |acc, d| format!("{}{}\n", acc, d))
-                .to_owned(),
-        );
+        match clipboard_rows(&self.devices) {
+            Ok(content) => copy_to_clipboard(content),
+            Err(e) => warn!("failed to copy devices: {e}"),
+        }
     }
 
-    pub async fn paste(&mut self, slint_device_tx: Sender<Vec<Device>>, content: String) {
+    pub async fn paste(
+        &mut self,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+        content: String,
+    ) {
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
+            .flexible(true)
             .from_reader(content.as_bytes());
 
-        let new_devices = rdr
-            .records()
-            .into_iter()
-            .filter_map(|r| {
-                if let Ok(record) = r {
-                    if let (Some(cc), Some(ui_type), Some(desc)) =
-                        (record.get(0), record.get(1), record.get(2))
-                    {
-                        Device::from_string_args(
-                            cc.to_string(),
-                            ui_type.to_string(),
-                            desc.to_string(),
-                        )
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+        let (mut new_devices, mut rejected) = (vec![], vec![]);
+        for record in rdr.records() {
+            let parsed = record.map_err(|e| e.to_string()).and_then(|record| {
+                let line = layout_file::line_of(&content, &record);
+                parse_record(&record).map_err(|e| e.at(line).to_string())
+            });
+            match parsed {
+                Ok(device) => new_devices.push(device),
+                Err(e) => rejected.push(e),
+            }
+        }
+
+        let mut report = format!(
+            "{} imported, {} rejected",
+            new_devices.len(),
+            rejected.len()
+        );
+        if !rejected.is_empty() {
+            warn!(?rejected, "rejected pasted devices");
+            report = format!("{report}: {}", rejected.join("; "));
+        }
+        info!("{report}");
+        let _ = status_tx.send_async(Status::Report(report)).await;
+
+        if !new_devices.is_empty() {
+            self.update_device(DeviceUpdate::Add(new_devices), slint_device_tx)
+                .await;
+        }
+    }
+
+    pub async fn save_file(&self, path: &Path, status_tx: Sender<Status>) {
+        let report = match layout_file::save(path, &self.devices) {
+            Ok(()) => {
+                info!(path = %path.display(), count = self.devices.len(), "layout saved");
+                format!("saved {} devices to {}", self.devices.len(), path.display())
+            }
+            Err(e) => {
+                warn!(path = %path.display(), "failed to save layout: {e}");
+                format!("could not save {}: {e}", path.display())
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+    }
+
+    // a layout file takes the place of whatever is exposed
+    pub async fn open_file(
+        &mut self,
+        path: &Path,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+    ) {
+        let report = match layout_file::open(path) {
+            Ok(opened) => {
+                info!(path = %path.display(), count = opened.devices.len(), "layout opened");
+                let report = rejected(
+                    format!(
+                        "opened {} devices from {}",
+                        opened.devices.len(),
+                        path.display()
+                    ),
+                    &opened.rejected,
+                );
+                self.update_device(DeviceUpdate::Replace(opened.devices), slint_device_tx)
+                    .await;
+                report
+            }
+            Err(e) => {
+                warn!(path = %path.display(), "failed to open layout: {e}");
+                format!("could not open {}: {e}", path.display())
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+    }
+
+    pub async fn save_preset(&self, name: &str, status_tx: Sender<Status>) {
+        let report = match presets::save(name, &self.devices) {
+            Ok(()) => {
+                info!(name, count = self.devices.len(), "preset saved");
+                format!("saved preset '{name}'")
+            }
+            Err(e) => {
+                warn!(name, "failed to save preset: {e}");
+                format!("could not save preset '{name}': {e}")
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+        let _ = status_tx.send_async(Status::Presets(presets::list())).await;
+    }
+
+    // like opening a layout file, the preset replaces whatever is exposed
+    pub async fn load_preset(
+        &mut self,
+        name: &str,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+    ) {
+        let report = match presets::load(name) {
+            Ok(opened) => {
+                info!(name, count = opened.devices.len(), "preset loaded");
+                self.update_device(DeviceUpdate::Replace(opened.devices), slint_device_tx)
+                    .await;
+                rejected(format!("loaded preset '{name}'"), &opened.rejected)
+            }
+            Err(e) => {
+                warn!(name, "failed to load preset: {e}");
+                format!("could not load preset '{name}': {e}")
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+    }
+
+    pub async fn delete_preset(&self, name: &str, status_tx: Sender<Status>) {
+        let report = match presets::delete(name) {
+            Ok(()) => {
+                info!(name, "preset deleted");
+                format!("deleted preset '{name}'")
+            }
+            Err(e) => {
+                warn!(name, "failed to delete preset: {e}");
+                format!("could not delete preset '{name}': {e}")
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+        let _ = status_tx.send_async(Status::Presets(presets::list())).await;
+    }
+
+    // cc map devices are added to what is already exposed
+    pub async fn import_cc_map(
+        &mut self,
+        path: &Path,
+        instrument: &str,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+    ) {
+        let report = match cc_map::import(path, instrument) {
+            Ok(import) => {
+                let mut report = format!(
+                    "{} imported, {} without cc, {} rejected",
+                    import.devices.len(),
+                    import.without_cc,
+                    import.rejected.len()
+                );
+                if !import.rejected.is_empty() {
+                    warn!(rejected = ?import.rejected, "rejected cc map rows");
+                    report = format!("{report}: {}", import.rejected.join("; "));
+                }
+                info!(path = %path.display(), instrument, "{report}");
+                if !import.devices.is_empty() {
+                    self.update_device(DeviceUpdate::Add(import.devices), slint_device_tx)
+                        .await;
+                }
+                report
+            }
+            Err(e) => {
+                warn!(path = %path.display(), "failed to import cc map: {e}");
+                format!("could not import {}: {e}", path.display())
+            }
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+    }
+
+    // runs locally first so it works without a server, the server then tells users to snap back
+    pub async fn panic(&mut self, midi_tx: Sender<MidiCmd>, status_tx: Sender<Status>) {
+        let mut channels = self.devices.iter().map(|d| d.channel).collect::<Vec<_>>();
+        channels.sort();
+        channels.dedup();
+        if channels.is_empty() {
+            channels = (0..16).collect();
+        }
+        warn!(?channels, "panic");
+        let _ = midi_tx.send_async(MidiCmd::Panic(channels)).await;
+        for frame in self.devices.iter().flat_map(|d| d.reset_outputs()) {
+            let command = match frame.kind {
+                MidiKind::Cc => MidiCmd::Signal(frame.channel, frame.number, frame.value),
+                MidiKind::Note => MidiCmd::Note(frame.channel, frame.number, frame.value),
+            };
+            let _ = midi_tx.send_async(command).await;
+        }
+
+        let report = match &self.login {
+            Some(login) => match Client::new()
+                .post(format!("http://{}/panic", login.url))
+                .query(&[("password", &login.pass)])
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+            {
+                Ok(_) => "panic: everything reset".to_string(),
+                Err(e) => {
+                    warn!("failed to reset users: {e}");
+                    format!("panic: reset locally, users not reset: {e}")
                 }
-            })
-            .collect::<Vec<Device>>();
+            },
+            None => "panic: reset locally".to_string(),
+        };
+        let _ = status_tx.send_async(Status::Report(report)).await;
+    }
 
-        self.update_device(DeviceUpdate::Add(new_devices), slint_device_tx)
-            .await;
+    // exposing a cc (or note) that is already exposed on the same channel edits it in place
+    pub async fn expose(
+        &mut self,
+        mut device: Device,
+        edit: DevicePatch,
+        layout: LayoutEdit,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+    ) {
+        device.layout = layout.apply(&device.layout);
+        let existing = self.devices.iter().find(|d| {
+            d.channel == device.channel && d.cc == device.cc && d.kind() == device.kind()
+        });
+        let (update, result) = match existing {
+            Some(existing) => {
+                // blank layout fields leave the device's alone
+                let edited = layout.apply(&existing.layout);
+                let edit = match edited != existing.layout {
+                    true => DevicePatch {
+                        layout: Some(edited),
+                        ..edit
+                    },
+                    false => edit,
+                };
+                let mut edited = existing.clone();
+                edited.apply(edit.clone());
+                (DeviceUpdate::Modify(existing.id, edit), edited)
+            }
+            None => (DeviceUpdate::Add(vec![device.clone()]), device),
+        };
+        if let Err(e) = result.validate() {
+            warn!(cc = result.cc, "not exposing: {e}");
+            let _ = status_tx
+                .send_async(Status::Report(format!("cc {}: {e}", result.cc)))
+                .await;
+            return;
+        }
+        self.update_device(update, slint_device_tx).await;
+    }
+
+    // updates from the control api, a modify is checked on the device it ends up as
+    pub async fn update_checked(
+        &mut self,
+        update: DeviceUpdate,
+        slint_device_tx: Sender<Vec<Device>>,
+        status_tx: Sender<Status>,
+    ) {
+        let checked = update.validate().and_then(|()| match &update {
+            DeviceUpdate::Modify(id, patch) => match self.devices.iter().find(|d| d.id == *id) {
+                Some(existing) => {
+                    let mut edited = existing.clone();
+                    edited.apply(patch.clone());
+                    edited.validate()
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        });
+        if let Err(e) = checked {
+            warn!("not updating: {e}");
+            let _ = status_tx
+                .send_async(Status::Report(format!("update rejected: {e}")))
+                .await;
+            return;
+        }
+        self.update_device(update, slint_device_tx).await;
     }
 
+    pub async fn move_device(
+        &mut self,
+        index: usize,
+        by: isize,
+        slint_device_tx: Sender<Vec<Device>>,
+    ) {
+        // rows are shown arranged, so moving steps to the neighbour shown there
+        let mut arranged = arrange(&self.devices);
+        let Some(from) = arranged.iter().position(|&i| i == index) else {
+            return;
+        };
+        let to = from.saturating_add_signed(by);
+        if to >= arranged.len() || to == from {
+            return;
+        }
+        let (a, b) = (&self.devices[index], &self.devices[arranged[to]]);
+        // devices change groups through the group field, not by moving
+        if (a.layout.page, &a.layout.group) != (b.layout.page, &b.layout.group) {
+            return;
+        }
+        let orders = (a.layout.order, b.layout.order);
+        arranged.swap(from, to);
+        let update = if orders.0 == orders.1 {
+            DeviceUpdate::Reorder(arranged.iter().map(|&i| self.devices[i].id).collect())
+        } else {
+            // the order field outranks the list position, so the two swap orders too
+            let mut devices = arranged
+                .iter()
+                .map(|&i| self.devices[i].clone())
+                .collect::<Vec<_>>();
+            devices[from].layout.order = orders.0;
+            devices[to].layout.order = orders.1;
+            DeviceUpdate::Replace(devices)
+        };
+        self.update_device(update, slint_device_tx).await;
+    }
+
+    // devices exposed on this side since logging into this session win, otherwise the
+    // layout the server kept is adopted
+    pub async fn reconcile(&mut self, restored: Vec<Device>, slint_device_tx: Sender<Vec<Device>>) {
+        if self.devices.is_empty() {
+            info!(
+                count = restored.len(),
+                "adopting devices restored by server"
+            );
+            self.devices = restored.clone();
+            let _ = slint_device_tx.send(restored);
+        } else {
+            info!(
+                count = self.devices.len(),
+                "pushing local devices to server"
+            );
+            let devices = self.devices.clone();
+            self.update_device(DeviceUpdate::Replace(devices), slint_device_tx)
+                .await;
+        }
+    }
+
+    // every edit that goes through here can be undone
     pub async fn update_device(
         &mut self,
         device: DeviceUpdate,
         slint_device_tx: Sender<Vec<Device>>,
     ) {
+        let before = self.devices.clone();
+        if self.sync(device, slint_device_tx).await {
+            self.record(before);
+        }
+    }
+
+    // true once the server took the update and the local list follows it
+    async fn sync(&mut self, device: DeviceUpdate, slint_device_tx: Sender<Vec<Device>>) -> bool {
         if let Some(login) = &self.login {
             let device_clone = device.clone();
+            debug!(update = ?device_clone, "sending device update");
 
-            if let Ok(response) = Client::new()
-                .post(format!(
-                    "http://{}/devices?password={}",
-                    login.url, login.pass
-                ))
+            match Client::new()
+                .post(format!("http://{}/devices", login.url))
+                .query(&[("password", &login.pass)])
                 .json(&device_clone)
                 .send()
                 .await
             {
-                if let Ok(devices) = response.json::<Vec<Device>>().await {
-                    //println!("{devices:?}");
-                    self.devices = devices.clone();
-                    let _ = slint_device_tx.send(devices);
-                };
-            };
+                Ok(response) => match response.json::<Vec<Device>>().await {
+                    Ok(devices) => {
+                        info!(count = devices.len(), "exposed devices updated");
+                        self.devices = devices.clone();
+                        let _ = slint_device_tx.send(devices);
+                        true
+                    }
+                    Err(e) => {
+                        warn!("unexpected device update response: {e}");
+                        false
+                    }
+                },
+                Err(e) => {
+                    warn!("device update failed: {e}");
+                    false
+                }
+            }
+        } else {
+            warn!("not logged in, device update dropped");
+            false
+        }
+    }
+}
+
+fn rejected(report: String, rejected: &[String]) -> String {
+    if rejected.is_empty() {
+        return report;
+    }
+    warn!(?rejected, "rejected devices");
+    format!(
+        "{report}, {} rejected: {}",
+        rejected.len(),
+        rejected.join("; ")
+    )
+}
+
+// cc,ui_type,description, quoted where needed so paste reads it back
+fn clipboard_rows(devices: &[Device]) -> Result<String, csv::Error> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    for d in devices {
+        wtr.write_record([
+            d.cc.to_string(),
+            d.ui_type.to_string(),
+            d.description.clone(),
+        ])?;
+    }
+    let content = wtr.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&content).to_string())
+}
+
+// cc,ui_type,description
+fn parse_record(record: &csv::StringRecord) -> Result<Device, DeviceParseError> {
+    match (record.get(0), record.get(1), record.get(2), record.len()) {
+        (Some(cc), Some(ui_type), Some(desc), 3) => {
+            Device::from_string_args(cc.to_string(), ui_type.to_string(), desc.to_string())
+        }
+        (.., found) => Err(DeviceParseError::ColumnCount { expected: 3, found }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use util::{Size, UIType};
+
+    #[test]
+    fn copied_rows_paste_back() {
+        let devices = vec![
+            Device::new(7, UIType::Knob, "volume".to_string()),
+            Device::new(10, UIType::Slide, "pan, \"wide\"".to_string()),
+        ];
+        let content = clipboard_rows(&devices).unwrap();
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(content.as_bytes());
+        let pasted = rdr
+            .records()
+            .map(|r| parse_record(&r.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(pasted.len(), 2);
+        assert_eq!(pasted[1].cc, 10);
+        assert_eq!(pasted[1].ui_type, UIType::Slide);
+        assert_eq!(pasted[1].description, "pan, \"wide\"");
+    }
+
+    // a server that takes adds, modifies, replaces and clears until it is told to fail
+    async fn fake_server() -> (String, Arc<AtomicBool>) {
+        let failing = Arc::new(AtomicBool::new(false));
+        let devices = Arc::new(Mutex::new(Vec::<Device>::new()));
+        let fail = failing.clone();
+        let app = Router::new().route(
+            "/devices",
+            post(move |Json(update): Json<DeviceUpdate>| async move {
+                if fail.load(Ordering::SeqCst) {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+                let mut devices = devices.lock().await;
+                match update {
+                    DeviceUpdate::Add(added) => devices.extend(added),
+                    DeviceUpdate::Modify(id, patch) => devices
+                        .iter_mut()
+                        .filter(|d| d.id == id)
+                        .for_each(|d| d.apply(patch.clone())),
+                    DeviceUpdate::Replace(replaced) => *devices = replaced,
+                    DeviceUpdate::Clear => devices.clear(),
+                    _ => {}
+                }
+                Ok(Json(devices.clone()))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, failing)
+    }
+
+    fn knob(cc: u8) -> Device {
+        Device::new(cc, UIType::Knob, format!("cc {cc}"))
+    }
+
+    fn ccs(state: &ExposedState) -> Vec<u8> {
+        state.devices.iter().map(|d| d.cc).collect()
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_step_through_synced_edits() {
+        let (url, _) = fake_server().await;
+        let (tx, _rx) = flume::unbounded();
+        let (status_tx, _status_rx) = flume::unbounded();
+        let mut state = ExposedState::new();
+        state.log_in(Login { url, ..login(None) }, tx.clone());
+
+        state
+            .update_device(DeviceUpdate::Add(vec![knob(1)]), tx.clone())
+            .await;
+        state
+            .update_device(DeviceUpdate::Add(vec![knob(2)]), tx.clone())
+            .await;
+        assert_eq!(ccs(&state), vec![1, 2]);
+
+        state.undo(tx.clone(), status_tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1]);
+        state.redo(tx.clone(), status_tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1, 2]);
+
+        // a new edit after undoing drops what could be redone
+        state.undo(tx.clone(), status_tx.clone()).await;
+        state
+            .update_device(DeviceUpdate::Add(vec![knob(3)]), tx.clone())
+            .await;
+        assert_eq!(ccs(&state), vec![1, 3]);
+        state.redo(tx.clone(), status_tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1, 3]);
+
+        // clearing is a step like any other
+        state.update_device(DeviceUpdate::Clear, tx.clone()).await;
+        assert!(state.devices.is_empty());
+        state.undo(tx, status_tx).await;
+        assert_eq!(ccs(&state), vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn a_note_doesnt_edit_a_cc_with_the_same_number() {
+        let (url, _) = fake_server().await;
+        let (tx, _rx) = flume::unbounded();
+        let (status_tx, _status_rx) = flume::unbounded();
+        let mut state = ExposedState::new();
+        state.log_in(Login { url, ..login(None) }, tx.clone());
+        let knob = knob(60);
+        state
+            .expose(
+                knob.clone(),
+                DevicePatch::replacing(&knob),
+                LayoutEdit::default(),
+                tx.clone(),
+                status_tx.clone(),
+            )
+            .await;
+        let keys = Device::new(60, UIType::Keys, "keys".to_string());
+        state
+            .expose(
+                keys.clone(),
+                DevicePatch::replacing(&keys),
+                LayoutEdit::default(),
+                tx,
+                status_tx,
+            )
+            .await;
+        let types = state.devices.iter().map(|d| d.ui_type).collect::<Vec<_>>();
+        assert_eq!(types, vec![UIType::Knob, UIType::Keys]);
+    }
+
+    #[tokio::test]
+    async fn blank_layout_fields_leave_the_device_alone() {
+        let (url, _) = fake_server().await;
+        let (tx, _rx) = flume::unbounded();
+        let (status_tx, _status_rx) = flume::unbounded();
+        let mut state = ExposedState::new();
+        state.log_in(Login { url, ..login(None) }, tx.clone());
+        let knob = Device { id: 1, ..knob(7) };
+        let grouped = LayoutEdit {
+            group: Some("mixer".to_string()),
+            color: Some("#ff8800".to_string()),
+            ..Default::default()
         };
+        state
+            .expose(
+                knob.clone(),
+                DevicePatch::default(),
+                grouped,
+                tx.clone(),
+                status_tx.clone(),
+            )
+            .await;
+        let paged = LayoutEdit {
+            page: Some(1),
+            size: Some(Size::Large),
+            ..Default::default()
+        };
+        state
+            .expose(knob, DevicePatch::default(), paged, tx, status_tx)
+            .await;
+        assert_eq!(state.devices.len(), 1);
+        let layout = &state.devices[0].layout;
+        assert_eq!(layout.group.as_deref(), Some("mixer"));
+        assert_eq!(layout.color.as_deref(), Some("#ff8800"));
+        assert_eq!((layout.page, layout.size), (1, Size::Large));
+    }
+
+    #[tokio::test]
+    async fn failed_updates_leave_devices_and_history_alone() {
+        let (url, failing) = fake_server().await;
+        let (tx, _rx) = flume::unbounded();
+        let (status_tx, _status_rx) = flume::unbounded();
+        let mut state = ExposedState::new();
+        state.log_in(Login { url, ..login(None) }, tx.clone());
+        state
+            .update_device(DeviceUpdate::Add(vec![knob(1)]), tx.clone())
+            .await;
+        state
+            .update_device(DeviceUpdate::Add(vec![knob(2)]), tx.clone())
+            .await;
+        state.undo(tx.clone(), status_tx.clone()).await;
+
+        failing.store(true, Ordering::SeqCst);
+        state.update_device(DeviceUpdate::Clear, tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1]);
+        // neither a step to undo nor the redo stack got touched
+        state.redo(tx.clone(), status_tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1]);
+
+        failing.store(false, Ordering::SeqCst);
+        state.redo(tx.clone(), status_tx.clone()).await;
+        assert_eq!(ccs(&state), vec![1, 2]);
+        state.undo(tx.clone(), status_tx.clone()).await;
+        state.undo(tx, status_tx).await;
+        assert!(state.devices.is_empty());
+    }
+
+    fn login(session: Option<&str>) -> Login {
+        Login {
+            url: "127.0.0.1:3000".to_string(),
+            pass: "x".to_string(),
+            session: session.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn logging_into_another_session_drops_the_local_layout() {
+        let (tx, rx) = flume::unbounded();
+        let mut state = ExposedState::new();
+        state.log_in(login(None), tx.clone());
+        state.devices = vec![Device::new(7, UIType::Knob, "volume".to_string())];
+
+        // reconnecting to the same session keeps it for reconcile to push
+        state.log_in(login(None), tx.clone());
+        assert_eq!(state.devices.len(), 1);
+
+        state.log_in(login(Some("other")), tx);
+        assert!(state.devices.is_empty());
+        assert_eq!(rx.drain().last(), Some(vec![]));
     }
 }
@@ -0,0 +1,383 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use flume::{bounded, Receiver, Sender};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use util::{Device, DeviceCmd, Login, Midi, MidiCmd};
+
+use crate::activity::Activity;
+use crate::control_api::{control_api, ControlApi};
+use crate::exposed_state::ExposedState;
+use crate::setters::Status;
+use crate::tasks::{device_task, midi_task, setup_task};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Debug)]
+#[command(about = "missing link between local DAW and a remote midi server")]
+pub struct Args {
+    /// run the bridge without the window, see --config
+    #[arg(long)]
+    pub headless: bool,
+    /// toml file with any of the options below, flags win over it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// server address, e.g. 127.0.0.1:3000
+    #[arg(long)]
+    pub url: Option<String>,
+    #[arg(long, env = "MIDISERV_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+    /// layout the server keeps for this host, its default one otherwise
+    #[arg(long)]
+    pub session: Option<String>,
+    /// index or part of the name of the midi output
+    #[arg(long)]
+    pub midi_port: Option<String>,
+    /// layout file exposed after logging in
+    #[arg(long)]
+    pub layout: Option<PathBuf>,
+    /// preset exposed after logging in, used when there is no layout
+    #[arg(long)]
+    pub preset: Option<String>,
+    #[arg(long)]
+    pub no_passthrough: bool,
+    /// serve the json control api on this address, e.g. 127.0.0.1:3100
+    #[arg(long)]
+    pub control_api: Option<SocketAddr>,
+    /// bearer token every control api request has to carry
+    #[arg(long, env = "MIDISERV_CONTROL_TOKEN", hide_env_values = true)]
+    pub control_api_token: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct Config {
+    url: Option<String>,
+    password: Option<String>,
+    session: Option<String>,
+    midi_port: Option<String>,
+    layout: Option<PathBuf>,
+    preset: Option<String>,
+    passthrough: Option<bool>,
+    control_api: Option<SocketAddr>,
+    control_api_token: Option<String>,
+}
+
+impl Config {
+    // read again on every SIGHUP
+    fn load(args: &Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => toml::from_str::<Config>(
+                &std::fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?,
+            )
+            .with_context(|| format!("parsing {}", path.display()))?,
+            None => Config::default(),
+        };
+        config.url = args.url.clone().or(config.url);
+        config.password = args.password.clone().or(config.password);
+        config.session = args.session.clone().or(config.session);
+        config.midi_port = args.midi_port.clone().or(config.midi_port);
+        config.layout = args.layout.clone().or(config.layout);
+        config.preset = args.preset.clone().or(config.preset);
+        config.control_api = args.control_api.or(config.control_api);
+        config.control_api_token = args.control_api_token.clone().or(config.control_api_token);
+        if args.no_passthrough {
+            config.passthrough = Some(false);
+        }
+        Ok(config)
+    }
+
+    fn login(&self) -> Result<Login> {
+        Ok(Login {
+            url: self
+                .url
+                .clone()
+                .context("no server url, set url in the config or pass --url")?,
+            pass: self
+                .password
+                .clone()
+                .context("no password, set password in the config or MIDISERV_PASSWORD")?,
+            session: self.session.clone(),
+        })
+    }
+}
+
+// what the loop is told from outside
+#[cfg_attr(not(unix), allow(dead_code))]
+enum Signal {
+    Stop,
+    Reload,
+    TogglePassthrough,
+    Panic,
+}
+
+#[cfg(unix)]
+struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+    toggle: tokio::signal::unix::Signal,
+    panic: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Signals {
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+            toggle: signal(SignalKind::user_defined1())?,
+            panic: signal(SignalKind::user_defined2())?,
+        })
+    }
+
+    async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Signal::Stop,
+            _ = self.terminate.recv() => Signal::Stop,
+            _ = self.hangup.recv() => Signal::Reload,
+            _ = self.toggle.recv() => Signal::TogglePassthrough,
+            _ = self.panic.recv() => Signal::Panic,
+        }
+    }
+}
+
+// only ctrl+c elsewhere, the control api covers the rest
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Signals)
+    }
+
+    async fn recv(&mut self) -> Signal {
+        let _ = tokio::signal::ctrl_c().await;
+        Signal::Stop
+    }
+}
+
+// the same tasks as the window runs, driven by the config and signals instead:
+// SIGINT/SIGTERM stop, SIGHUP reloads the config, SIGUSR1 toggles passthrough, SIGUSR2 panics.
+// without unix signals only ctrl+c is there
+pub fn run(args: Args) -> Result<()> {
+    let mut config = Config::load(&args)?;
+    let mut login = config.login()?;
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let midi = Arc::new(Mutex::new(Midi::new()));
+    let passthrough = Arc::new(Mutex::new(config.passthrough.unwrap_or(true)));
+
+    let (shutdown_tx, shutdown_rx): (Sender<bool>, Receiver<bool>) = bounded(10);
+    let (midi_tx, midi_rx): (Sender<MidiCmd>, Receiver<MidiCmd>) = bounded(10);
+    let (login_tx, login_rx): (Sender<Login>, Receiver<Login>) = bounded(10);
+    let (logout_tx, logout_rx): (Sender<()>, Receiver<()>) = bounded(10);
+    let (login_response_tx, login_response_rx): (Sender<bool>, Receiver<bool>) = bounded(10);
+    let (status_tx, status_rx): (Sender<Status>, Receiver<Status>) = bounded(10);
+    let (device_tx, device_rx): (Sender<DeviceCmd>, Receiver<DeviceCmd>) = bounded(10);
+    // nothing shows the list, it only gets logged
+    let (slint_device_tx, slint_device_rx): (Sender<Vec<Device>>, Receiver<Vec<Device>>) =
+        bounded(1);
+    let (activity_tx, activity_rx): (Sender<Activity>, Receiver<Activity>) = bounded(100);
+
+    setup_task(
+        &rt,
+        shutdown_rx.clone(),
+        login_rx,
+        midi_tx.clone(),
+        login_response_tx,
+        status_tx.clone(),
+        passthrough.clone(),
+        logout_rx,
+        activity_tx,
+    );
+    device_task(
+        &rt,
+        shutdown_rx.clone(),
+        device_rx,
+        ExposedState::new(),
+        slint_device_tx,
+        status_tx.clone(),
+        midi_tx.clone(),
+    );
+    midi_task(&rt, shutdown_rx, midi.clone(), midi_rx, status_tx.clone());
+    if let Some(addr) = config.control_api {
+        control_api(
+            &rt,
+            addr,
+            config.control_api_token.clone(),
+            ControlApi {
+                device_tx: device_tx.clone(),
+                midi_tx: midi_tx.clone(),
+                status_tx,
+                passthrough: passthrough.clone(),
+                midi: midi.clone(),
+            },
+        );
+    }
+
+    rt.block_on(async {
+        let mut signals = Signals::new()?;
+        // ticks while disconnected, the first try is a full delay away
+        let mut reconnect = tokio::time::interval(RECONNECT_DELAY);
+        reconnect.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        reconnect.reset();
+
+        select_port(&midi, &midi_tx, config.midi_port.as_deref()).await;
+        let mut connected = log_in(&login, &login_tx, &login_response_rx, &device_tx).await;
+        // the configured layout goes out with the first login that works, and again after a reload
+        let mut expose_pending = true;
+        if connected {
+            expose_configured(&config, &device_tx).await;
+            expose_pending = false;
+        }
+
+        loop {
+            tokio::select! {
+                signal = signals.recv() => match signal {
+                    Signal::Stop => break,
+                    Signal::Reload => {
+                        info!("reloading config");
+                        let reloaded = match Config::load(&args).and_then(|c| Ok((c.login()?, c))) {
+                            Ok(reloaded) => reloaded,
+                            Err(e) => {
+                                warn!("keeping the old config: {e:#}");
+                                continue;
+                            }
+                        };
+                        let (new_login, new_config) = reloaded;
+                        if new_config.midi_port != config.midi_port {
+                            select_port(&midi, &midi_tx, new_config.midi_port.as_deref()).await;
+                        }
+                        if let Some(p) = new_config.passthrough {
+                            *passthrough.lock().await = p;
+                        }
+                        if new_login.url != login.url
+                            || new_login.pass != login.pass
+                            || new_login.session != login.session
+                        {
+                            let _ = logout_tx.send_async(()).await;
+                            login = new_login;
+                            connected = log_in(&login, &login_tx, &login_response_rx, &device_tx).await;
+                        }
+                        config = new_config;
+                        expose_pending = !connected;
+                        if connected {
+                            expose_configured(&config, &device_tx).await;
+                        }
+                    }
+                    Signal::Panic => {
+                        let _ = device_tx.send_async(DeviceCmd::Panic).await;
+                    }
+                    Signal::TogglePassthrough => {
+                        let mut p = passthrough.lock().await;
+                        *p = !*p;
+                        info!(passthrough = *p, "passthrough toggled");
+                    }
+                },
+                _ = reconnect.tick(), if !connected => {
+                    connected = log_in(&login, &login_tx, &login_response_rx, &device_tx).await;
+                    if connected && expose_pending {
+                        expose_configured(&config, &device_tx).await;
+                        expose_pending = false;
+                    }
+                }
+                activity = activity_rx.recv_async() => {
+                    match activity {
+                        Ok(Activity::Value(frame, _)) => {
+                            debug!(user = frame.user, device = frame.device, number = frame.number, value = frame.value, "value");
+                        }
+                        Ok(Activity::Requested(user, client, device)) => {
+                            info!(user, %client, device, "approval requested, POST /approve {{\"device\": {device}, \"client\": \"{client}\"}} on the control api");
+                        }
+                        _ => {}
+                    }
+                }
+                devices = slint_device_rx.recv_async() => {
+                    if let Ok(devices) = devices {
+                        for d in devices {
+                            info!(id = d.id, channel = d.channel, cc = d.cc, ui_type = %d.ui_type, description = %d.description, "exposed");
+                        }
+                    }
+                }
+                status = status_rx.recv_async() => {
+                    match status {
+                        Ok(Status::Connection(c)) => {
+                            info!(connected = c, "server connection");
+                            if connected && !c {
+                                reconnect.reset();
+                            }
+                            connected = c;
+                        }
+                        Ok(Status::Text(name)) => info!(server_name = %name, "logged in"),
+                        Ok(Status::Restored(devices)) => {
+                            let _ = device_tx.send_async(DeviceCmd::Reconcile(devices)).await;
+                        }
+                        Ok(Status::Report(report)) => info!("{report}"),
+                        Ok(Status::Passthrough(p)) => info!(passthrough = p, "passthrough set"),
+                        Ok(Status::Presets(_)) | Ok(Status::Learned(_)) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+
+        info!("shutting down");
+        let _ = logout_tx.send_async(()).await;
+        // every task takes its own shutdown message off the shared channel
+        for _ in 0..3 {
+            let _ = shutdown_tx.send_async(true).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        anyhow::Ok(())
+    })
+}
+
+async fn log_in(
+    login: &Login,
+    login_tx: &Sender<Login>,
+    login_response_rx: &Receiver<bool>,
+    device_tx: &Sender<DeviceCmd>,
+) -> bool {
+    info!(url = %login.url, "logging in");
+    let _ = login_tx.send_async(login.clone()).await;
+    let logged_in = login_response_rx.recv_async().await.unwrap_or(false);
+    if logged_in {
+        let _ = device_tx.send_async(DeviceCmd::Login(login.clone())).await;
+    } else {
+        warn!(retry_in = ?RECONNECT_DELAY, "login failed");
+    }
+    logged_in
+}
+
+async fn expose_configured(config: &Config, device_tx: &Sender<DeviceCmd>) {
+    let command = match (&config.layout, &config.preset) {
+        (Some(layout), _) => DeviceCmd::Open(layout.clone()),
+        (None, Some(preset)) => DeviceCmd::LoadPreset(preset.clone()),
+        (None, None) => return,
+    };
+    let _ = device_tx.send_async(command).await;
+}
+
+// ports are listed as "id|name"
+async fn select_port(midi: &Mutex<Midi>, midi_tx: &Sender<MidiCmd>, port: Option<&str>) {
+    let Some(port) = port else {
+        warn!("no midi port configured, values go nowhere");
+        return;
+    };
+    let mut midi = midi.lock().await;
+    match midi.find_port(port) {
+        Some(i) => {
+            info!(port = %midi.get_ports()[i], "selecting midi port");
+            let _ = midi_tx.send_async(MidiCmd::Port(i)).await;
+        }
+        None => warn!(port, available = ?midi.get_ports(), "midi port not found"),
+    }
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use util::{Conflict, Curve, Device, DeviceParseError, Size, UIParams};
+
+#[derive(Error, Debug)]
+pub enum LayoutFileError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    TomlRead(#[from] toml::de::Error),
+    #[error("{0}")]
+    TomlWrite(#[from] toml::ser::Error),
+    #[error("{0}")]
+    Csv(#[from] csv::Error),
+    #[error("{0}")]
+    Device(#[from] DeviceParseError),
+    #[error("unknown layout format '{0}', use .json, .toml or .csv")]
+    UnknownFormat(String),
+}
+
+enum Format {
+    Json,
+    Toml,
+    Csv,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Self, LayoutFileError> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match ext.as_str() {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(LayoutFileError::UnknownFormat(ext)),
+        }
+    }
+}
+
+// toml has no top level arrays, devices go under [[devices]]
+#[derive(Serialize, Deserialize)]
+struct TomlLayout {
+    #[serde(default)]
+    devices: Vec<Device>,
+}
+
+// the flat part of a device, params in the short form the expose form takes
+#[derive(Serialize, Deserialize)]
+struct CsvRow {
+    cc: String,
+    ui_type: String,
+    description: String,
+    #[serde(default)]
+    channel: u8,
+    #[serde(default)]
+    min: u8,
+    #[serde(default = "CsvRow::max_value")]
+    max: u8,
+    #[serde(default)]
+    default: u8,
+    #[serde(default)]
+    invert: bool,
+    #[serde(default)]
+    curve: String,
+    #[serde(default)]
+    group: String,
+    #[serde(default)]
+    page: u8,
+    #[serde(default)]
+    order: i16,
+    #[serde(default)]
+    color: String,
+    #[serde(default)]
+    size: String,
+    #[serde(default)]
+    icon: String,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    slew: u16,
+    #[serde(default)]
+    conflict: String,
+    #[serde(default)]
+    params: String,
+}
+
+impl CsvRow {
+    fn max_value() -> u8 {
+        127
+    }
+
+    fn from_device(d: &Device) -> Self {
+        CsvRow {
+            cc: d.cc.to_string(),
+            ui_type: d.ui_type.to_string(),
+            description: d.description.clone(),
+            channel: d.channel,
+            min: d.min,
+            max: d.max,
+            default: d.default,
+            invert: d.invert,
+            curve: d.curve.to_string(),
+            group: d.layout.group.clone().unwrap_or_default(),
+            page: d.layout.page,
+            order: d.layout.order,
+            color: d.layout.color.clone().unwrap_or_default(),
+            size: d.layout.size.to_string(),
+            icon: d.layout.icon.clone().unwrap_or_default(),
+            locked: d.locked,
+            slew: d.slew,
+            conflict: d.conflict.to_string(),
+            params: d.params.to_string(),
+        }
+    }
+
+    fn into_device(self) -> Result<Device, DeviceParseError> {
+        let mut device = Device::from_string_args(self.cc, self.ui_type, self.description)?;
+        device.channel = self.channel;
+        device.min = self.min;
+        device.max = self.max;
+        device.default = self.default;
+        device.invert = self.invert;
+        device.curve = match self.curve.trim() {
+            "" => Curve::Linear,
+            curve => curve
+                .parse()
+                .map_err(|_| DeviceParseError::UnknownCurve(curve.to_string()))?,
+        };
+        device.layout.group = Some(self.group).filter(|g| !g.is_empty());
+        device.layout.page = self.page;
+        device.layout.order = self.order;
+        device.layout.color = Some(self.color).filter(|c| !c.is_empty());
+        device.layout.size = match self.size.trim() {
+            "" => Size::default(),
+            size => size
+                .parse()
+                .map_err(|_| DeviceParseError::UnknownSize(size.to_string()))?,
+        };
+        device.layout.icon = Some(self.icon).filter(|i| !i.is_empty());
+        device.locked = self.locked;
+        device.slew = self.slew;
+        device.conflict = match self.conflict.trim() {
+            "" => Conflict::default(),
+            conflict => conflict
+                .parse()
+                .map_err(|_| DeviceParseError::UnknownConflict(conflict.to_string()))?,
+        };
+        device.params = UIParams::parse(device.ui_type, device.cc, &self.params)?;
+        device.validate()?;
+        Ok(device)
+    }
+}
+
+// csv doesn't count blank lines, so the line is worked out from where the record starts
+pub fn line_of(content: &str, record: &csv::StringRecord) -> u64 {
+    let Some(start) = record.position().map(|p| p.byte() as usize) else {
+        return 0;
+    };
+    let (before, after) = content.split_at(start.min(content.len()));
+    let blank = after
+        .chars()
+        .take_while(|c| *c == '\n' || *c == '\r')
+        .filter(|c| *c == '\n')
+        .count();
+    (before.matches('\n').count() + blank) as u64 + 1
+}
+
+// where the control api reads and writes layouts, next to the presets
+pub fn layout_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("midiserv")
+        .join("layouts")
+}
+
+// ids belong to the server they were exposed on, files don't keep them
+pub fn save(path: &Path, devices: &[Device]) -> Result<(), LayoutFileError> {
+    let devices = devices
+        .iter()
+        .cloned()
+        .map(|d| Device { id: 0, ..d })
+        .collect::<Vec<_>>();
+
+    let content = match Format::of(path)? {
+        Format::Json => serde_json::to_string_pretty(&devices)?,
+        Format::Toml => toml::to_string_pretty(&TomlLayout { devices })?,
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_writer(vec![]);
+            for d in &devices {
+                wtr.serialize(CsvRow::from_device(d))?;
+            }
+            String::from_utf8_lossy(&wtr.into_inner().map_err(|e| e.into_error())?).to_string()
+        }
+    };
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+// devices that don't check out are left out and come back as rejected, like a paste
+pub struct Opened {
+    pub devices: Vec<Device>,
+    pub rejected: Vec<String>,
+}
+
+pub fn open(path: &Path) -> Result<Opened, LayoutFileError> {
+    let content = std::fs::read_to_string(path)?;
+    let parsed = match Format::of(path)? {
+        Format::Json => entries(serde_json::from_str::<Vec<Device>>(&content)?),
+        Format::Toml => entries(toml::from_str::<TomlLayout>(&content)?.devices),
+        Format::Csv => {
+            let mut rdr = csv::Reader::from_reader(content.as_bytes());
+            let headers = rdr.headers()?.clone();
+            rdr.records()
+                .map(|record| {
+                    let record = record.map_err(|e| e.to_string())?;
+                    let line = line_of(&content, &record);
+                    let row: CsvRow = record
+                        .deserialize(Some(&headers))
+                        .map_err(|e| format!("line {line} {e}"))?;
+                    row.into_device().map_err(|e| e.at(line).to_string())
+                })
+                .collect()
+        }
+    };
+    let (mut devices, mut rejected) = (vec![], vec![]);
+    for device in parsed {
+        match device {
+            Ok(device) => devices.push(Device { id: 0, ..device }),
+            Err(e) => rejected.push(e),
+        }
+    }
+    Ok(Opened { devices, rejected })
+}
+
+fn entries(devices: Vec<Device>) -> Vec<Result<Device, String>> {
+    devices
+        .into_iter()
+        .enumerate()
+        .map(|(i, d)| match d.validate() {
+            Ok(()) => Ok(d),
+            Err(e) => Err(e.entry(i + 1).to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::Layout;
+
+    fn lines(content: &str) -> Vec<u64> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(content.as_bytes())
+            .records()
+            .map(|r| line_of(content, &r.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn lines_count_blank_lines() {
+        assert_eq!(lines("1,knob,a\n2,knob,b\n"), vec![1, 2]);
+        assert_eq!(lines("\n1,knob,a\n\n\r\n2,knob,b\n"), vec![2, 5]);
+    }
+
+    fn opened(name: &str, content: &str) -> Opened {
+        let path = std::env::temp_dir().join(format!("midiserv-{}-{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let opened = open(&path);
+        let _ = std::fs::remove_file(&path);
+        opened.unwrap()
+    }
+
+    fn saved_and_opened(name: &str, devices: &[Device]) -> Opened {
+        let path = std::env::temp_dir().join(format!("midiserv-{}-{name}", std::process::id()));
+        save(&path, devices).unwrap();
+        let opened = open(&path);
+        let _ = std::fs::remove_file(&path);
+        opened.unwrap()
+    }
+
+    // one of each ui type with every flat field moved off its default
+    fn layout() -> Vec<Device> {
+        let mut knob =
+            Device::from_string_args("7".into(), "knob".into(), "volume".into()).unwrap();
+        knob.id = 3;
+        knob.channel = 2;
+        knob.min = 10;
+        knob.max = 100;
+        knob.default = 64;
+        knob.invert = true;
+        knob.curve = "log".parse().unwrap();
+        knob.slew = 250;
+        knob.locked = true;
+        knob.conflict = "hold 5".parse().unwrap();
+        knob.layout = Layout {
+            page: 1,
+            group: Some("mixer".to_string()),
+            order: -2,
+            color: Some("#ff8800".to_string()),
+            size: Size::Large,
+            icon: Some("fader".to_string()),
+        };
+        let keys = Device::from_string_args("0".into(), "keys".into(), "piano".into()).unwrap();
+        vec![knob, keys]
+    }
+
+    fn without_ids(devices: Vec<Device>) -> Vec<Device> {
+        devices.into_iter().map(|d| Device { id: 0, ..d }).collect()
+    }
+
+    #[test]
+    fn json_layouts_round_trip() {
+        let opened = saved_and_opened("round.json", &layout());
+        assert!(opened.rejected.is_empty(), "{:?}", opened.rejected);
+        assert_eq!(opened.devices, without_ids(layout()));
+    }
+
+    #[test]
+    fn toml_layouts_round_trip() {
+        let opened = saved_and_opened("round.toml", &layout());
+        assert!(opened.rejected.is_empty(), "{:?}", opened.rejected);
+        assert_eq!(opened.devices, without_ids(layout()));
+    }
+
+    #[test]
+    fn csv_layouts_round_trip() {
+        let opened = saved_and_opened("round.csv", &layout());
+        assert!(opened.rejected.is_empty(), "{:?}", opened.rejected);
+        assert_eq!(opened.devices, without_ids(layout()));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let opened = opened(
+            "lines.csv",
+            "cc,ui_type,description\n7,knob,volume\n\n10,wheel,pan\n",
+        );
+        assert_eq!(opened.devices.len(), 1);
+        assert_eq!(opened.rejected, vec!["line 4 unknown ui type 'wheel'"]);
+    }
+
+    #[test]
+    fn json_devices_are_checked() {
+        let opened = opened(
+            "checked.json",
+            r#"[{"cc": 7, "ui_type": "Knob", "description": "volume"},
+                {"cc": 8, "ui_type": "Knob", "description": "pan", "min": 100, "max": 20}]"#,
+        );
+        assert_eq!(opened.devices.len(), 1);
+        assert_eq!(opened.rejected.len(), 1);
+        assert!(
+            opened.rejected[0].starts_with("device 2 min 100"),
+            "{:?}",
+            opened.rejected
+        );
+    }
+
+    #[test]
+    fn csv_rows_are_checked() {
+        let opened = opened(
+            "checked.csv",
+            "cc,ui_type,description,min,max,default,curve\n\
+             7,knob,volume,0,127,0,wobbly\n\
+             8,knob,pan,10,20,30,\n\
+             9,knob,gain,10,20,15,log\n",
+        );
+        assert_eq!(opened.devices.len(), 1);
+        assert_eq!(
+            opened.rejected,
+            vec![
+                "line 2 unknown curve 'wobbly'",
+                "line 3 default 30 is outside 10..=20"
+            ]
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+pub fn log_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("midiserv")
+        .join("logs")
+}
+
+// logs go both to stderr (stdout when headless) and to a daily rotated file,
+// the guard has to live until shutdown
+pub fn init_logging(headless: bool) -> WorkerGuard {
+    let (file_writer, guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::daily(log_dir(), "local.log"));
+
+    let console = if headless {
+        fmt::layer().with_writer(BoxMakeWriter::new(std::io::stdout))
+    } else {
+        fmt::layer().with_writer(BoxMakeWriter::new(std::io::stderr))
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(console)
+        .with(fmt::layer().with_ansi(false).with_writer(file_writer))
+        .init();
+
+    guard
+}
@@ -1,16 +1,29 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod activity;
+mod cc_map;
+mod control_api;
+mod expose_form;
 mod exposed_state;
+mod headless;
+mod layout_file;
+mod logging;
+mod presets;
 mod setters;
+mod slew;
 mod tasks;
 mod ui_handlers;
 
+use activity::{activity_monitor, Activity, DeviceNames};
 use anyhow::Result;
+use clap::Parser;
+use control_api::{control_api, ControlApi};
 use exposed_state::ExposedState;
 use flume::bounded;
 use flume::Receiver;
 use flume::Sender;
-use setters::{connection_status, init_ui_types, set_ports, Status};
+use logging::{init_logging, log_dir};
+use setters::{connection_status, init_ui_types, set_ports, set_presets, Status};
 use slint::CloseRequestResponse;
 use slint::ComponentHandle;
 use slint::ModelRc;
@@ -25,6 +38,8 @@ use tasks::device_task;
 use tasks::midi_task;
 use tasks::setup_task;
 use tokio::sync::Mutex;
+use tracing::info;
+use util::arrange;
 use util::Device;
 use util::DeviceCmd;
 use util::DeviceUpdate;
@@ -36,9 +51,16 @@ slint::include_modules!();
 
 fn main() -> Result<(), Box<dyn Error>> {
     // INIT
+    let args = headless::Args::parse();
+    let _log_guard = init_logging(args.headless);
+    info!(log_dir = %log_dir().display(), "starting midiserv");
+    if args.headless {
+        return Ok(headless::run(args)?);
+    }
     let app = AppWindow::new()?;
     let midi = Arc::new(Mutex::new(Midi::new()));
     init_ui_types(app.clone_strong());
+    set_presets(&app, presets::list());
     let state = ExposedState::new();
 
     let rt = tokio::runtime::Runtime::new().unwrap();
@@ -62,6 +84,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let (device_tx, device_rx): (Sender<DeviceCmd>, Receiver<DeviceCmd>) = bounded(10);
     let (slint_device_tx, slint_device_rx): (Sender<Vec<Device>>, Receiver<Vec<Device>>) =
         bounded(1);
+    let (activity_tx, activity_rx): (Sender<Activity>, Receiver<Activity>) = bounded(100);
 
     // TASKS
     let app_clone = app.clone_strong();
@@ -75,23 +98,84 @@ fn main() -> Result<(), Box<dyn Error>> {
         status_tx.clone(),
         passthrough.clone(),
         logout_rx.clone(),
+        activity_tx,
     );
-    device_task(&rt, shutdown_rx.clone(), device_rx, state, slint_device_tx);
-    midi_task(&rt, shutdown_rx.clone(), midi.clone(), midi_rx);
+    device_task(
+        &rt,
+        shutdown_rx.clone(),
+        device_rx,
+        state,
+        slint_device_tx,
+        status_tx.clone(),
+        midi_tx.clone(),
+    );
+    midi_task(
+        &rt,
+        shutdown_rx.clone(),
+        midi.clone(),
+        midi_rx,
+        status_tx.clone(),
+    );
+    if let Some(addr) = args.control_api {
+        control_api(
+            &rt,
+            addr,
+            args.control_api_token.clone(),
+            ControlApi {
+                device_tx: device_tx.clone(),
+                midi_tx: midi_tx.clone(),
+                status_tx: status_tx.clone(),
+                passthrough: passthrough.clone(),
+                midi: midi.clone(),
+            },
+        );
+    }
 
     let exp_dev = Rc::new(VecModel::from(vec![]));
     app.set_exposed_devices(ModelRc::from(Rc::clone(&exp_dev)));
+    let names = DeviceNames::default();
+    activity_monitor(
+        app.clone_strong(),
+        activity_rx,
+        names.clone(),
+        device_tx.clone(),
+    );
 
     let _ = slint::spawn_local(async move {
         while let Ok(devices) = slint_device_rx.recv_async().await {
-            //println!("devices {:?}", devices);
-            let _ = &exp_dev.set_vec(
-                devices
-                    .iter()
-                    .map(|d| format!("{}|{}|{}", d.cc, d.ui_type, d.description))
-                    .map(SharedString::from)
-                    .collect::<Vec<SharedString>>(),
-            );
+            *names.borrow_mut() = devices
+                .iter()
+                .map(|d| (d.id, d.description.clone()))
+                .collect();
+            // a header row starts every page/group, the ungrouped ones go without a name
+            let mut last = None;
+            let rows = arrange(&devices)
+                .into_iter()
+                .map(|i| {
+                    let d = &devices[i];
+                    let key = (d.layout.page, d.layout.group.clone());
+                    let header = last.as_ref() != Some(&key);
+                    last = Some(key);
+                    ExposedRow {
+                        text: SharedString::from(format!(
+                            "{}|{}|{}",
+                            d.cc, d.ui_type, d.description
+                        )),
+                        index: i as i32,
+                        locked: d.locked,
+                        group: SharedString::from(match &d.layout.group {
+                            Some(group) if d.layout.page > 0 => {
+                                format!("p{} {}", d.layout.page, group)
+                            }
+                            Some(group) => group.clone(),
+                            None if d.layout.page > 0 => format!("p{}", d.layout.page),
+                            None => String::new(),
+                        }),
+                        header,
+                    }
+                })
+                .collect::<Vec<ExposedRow>>();
+            let _ = &exp_dev.set_vec(rows);
         }
     });
 
@@ -101,6 +185,11 @@ fn main() -> Result<(), Box<dyn Error>> {
         let _ = tx_clone.send(MidiCmd::Port(port as usize));
     });
 
+    let tx_clone = midi_tx.clone();
+    app.global::<AppState>().on_midi_learn(move || {
+        let _ = tx_clone.send(MidiCmd::Learn);
+    });
+
     let tx_clone = midi_tx.clone();
     app.global::<AppState>()
         .on_send_dummy_cc(move |controller| {
@@ -131,22 +220,35 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // UI - EXPOSED DEVICES
     let device_tx_clone = device_tx.clone();
+    let status_tx_clone = status_tx.clone();
     app.global::<AppState>()
-        .on_expose_device(move |cc, ui_type, description| {
-            if let Some(d) = Device::from_string_args(
-                cc.to_string(),
-                ui_type.to_string(),
-                description.to_string(),
-            ) {
-                let _ = device_tx_clone.send(DeviceCmd::Update(DeviceUpdate::Add(vec![d])));
+        .on_expose_device(move |fields| match expose_form::parse(&fields) {
+            Ok((device, edit, layout)) => {
+                let _ = device_tx_clone.send(DeviceCmd::Expose(device, edit, layout));
+            }
+            Err(e) => {
+                let _ = status_tx_clone.try_send(Status::Report(e));
             }
         });
 
     let device_tx_clone = device_tx.clone();
     app.global::<AppState>().on_hide_device(move |i| {
         if let Ok(i) = i.parse::<usize>() {
-            let update = DeviceUpdate::Remove(vec![i]);
-            let _ = device_tx_clone.send(DeviceCmd::Update(update));
+            let _ = device_tx_clone.send(DeviceCmd::Hide(vec![i]));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_toggle_lock(move |i| {
+        if let Ok(i) = i.parse::<usize>() {
+            let _ = device_tx_clone.send(DeviceCmd::ToggleLock(i));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_move_device(move |i, by| {
+        if let Ok(i) = i.parse::<usize>() {
+            let _ = device_tx_clone.send(DeviceCmd::Move(i, by as isize));
         }
     });
 
@@ -155,6 +257,67 @@ fn main() -> Result<(), Box<dyn Error>> {
         let _ = device_tx_clone.send(DeviceCmd::Paste);
     });
 
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_save_layout(move |path| {
+        if !path.trim().is_empty() {
+            let _ = device_tx_clone.send(DeviceCmd::Save(path.trim().into()));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_open_layout(move |path| {
+        if !path.trim().is_empty() {
+            let _ = device_tx_clone.send(DeviceCmd::Open(path.trim().into()));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_save_preset(move |name| {
+        if !name.trim().is_empty() {
+            let _ = device_tx_clone.send(DeviceCmd::SavePreset(name.trim().to_string()));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_load_preset(move |name| {
+        if !name.is_empty() {
+            let _ = device_tx_clone.send(DeviceCmd::LoadPreset(name.to_string()));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_delete_preset(move |name| {
+        if !name.is_empty() {
+            let _ = device_tx_clone.send(DeviceCmd::DeletePreset(name.to_string()));
+        }
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>()
+        .on_import_cc_map(move |path, instrument| {
+            if !path.trim().is_empty() {
+                let _ = device_tx_clone.send(DeviceCmd::ImportCcMap(
+                    path.trim().into(),
+                    instrument.to_string(),
+                ));
+            }
+        });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_panic(move || {
+        let _ = device_tx_clone.send(DeviceCmd::Panic);
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_undo(move || {
+        let _ = device_tx_clone.send(DeviceCmd::Undo);
+    });
+
+    let device_tx_clone = device_tx.clone();
+    app.global::<AppState>().on_redo(move || {
+        let _ = device_tx_clone.send(DeviceCmd::Redo);
+    });
+
     let device_tx_clone = device_tx.clone();
     app.global::<AppState>().on_clear_all(move || {
         let _ = device_tx_clone.send(DeviceCmd::Update(DeviceUpdate::Clear));
@@ -167,23 +330,25 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let device_tx_clone = device_tx.clone();
     let app_clone = app.clone_strong();
-    app.global::<AppState>().on_login(move |url, pass| {
-        set_ports(app_clone.clone_strong(), midi.clone());
+    app.global::<AppState>()
+        .on_login(move |url, pass, session| {
+            set_ports(app_clone.clone_strong(), midi.clone());
 
-        let login_payload = Login {
-            url: url.to_string(),
-            pass: pass.to_string(),
-        };
-        let _ = login_tx.send(login_payload.clone());
+            let login_payload = Login {
+                url: url.to_string(),
+                pass: pass.to_string(),
+                session: Some(session.trim().to_string()).filter(|s| !s.is_empty()),
+            };
+            let _ = login_tx.send(login_payload.clone());
 
-        let logged_in = login_response_rx.recv().unwrap_or(false);
+            let logged_in = login_response_rx.recv().unwrap_or(false);
 
-        if logged_in {
-            let _ = device_tx_clone.send(DeviceCmd::Login(login_payload));
-        };
+            if logged_in {
+                let _ = device_tx_clone.send(DeviceCmd::Login(login_payload));
+            };
 
-        app_clone.global::<AppState>().set_logged_in(logged_in);
-    });
+            app_clone.global::<AppState>().set_logged_in(logged_in);
+        });
 
     // STOP
     let app_clone = app.clone_strong();
@@ -201,6 +366,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     });
 
     let _ = app.run();
+    info!("shutting down");
     sleep(Duration::from_millis(100));
     Ok(())
 }
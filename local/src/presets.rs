@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+use util::Device;
+
+use crate::layout_file::{self, LayoutFileError, Opened};
+
+// one toml layout file per preset, named after it
+pub fn preset_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("midiserv")
+        .join("presets")
+}
+
+fn path(name: &str) -> PathBuf {
+    let file = name
+        .trim()
+        .replace(['/', '\\', ':'], "_")
+        .trim_start_matches('.')
+        .to_string();
+    preset_dir().join(format!("{file}.toml"))
+}
+
+pub fn list() -> Vec<String> {
+    let mut names = std::fs::read_dir(preset_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+                .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|n| n.to_lowercase());
+    names
+}
+
+pub fn save(name: &str, devices: &[Device]) -> Result<(), LayoutFileError> {
+    std::fs::create_dir_all(preset_dir())?;
+    layout_file::save(&path(name), devices)
+}
+
+pub fn load(name: &str) -> Result<Opened, LayoutFileError> {
+    layout_file::open(&path(name))
+}
+
+pub fn delete(name: &str) -> Result<(), LayoutFileError> {
+    std::fs::remove_file(path(name))?;
+    Ok(())
+}
@@ -3,7 +3,7 @@ use flume::{Receiver, Sender};
 use slint::{ComponentHandle, ModelRc, SharedString, VecModel};
 use std::{rc::Rc, sync::Arc};
 use tokio::sync::Mutex;
-use util::{DeviceCmd, DeviceUpdate, Midi, UIType};
+use util::{Device, DeviceCmd, Learned, Midi, MidiKind, UIParams, UIType};
 
 pub fn set_ports(app: AppWindow, midi: Arc<Mutex<Midi>>) {
     let ports = Rc::new(
@@ -27,9 +27,26 @@ pub fn init_ui_types(app: AppWindow) {
     app.set_ui_types(ModelRc::from(Rc::clone(&ui_types)));
 }
 
+pub fn set_presets(app: &AppWindow, names: Vec<String>) {
+    let presets = names
+        .iter()
+        .map(SharedString::from)
+        .collect::<Vec<SharedString>>();
+    app.global::<AppState>()
+        .set_presets(ModelRc::from(Rc::new(VecModel::from(presets))));
+}
+
 pub enum Status {
     Connection(bool),
     Text(String),
+    Restored(Vec<Device>),
+    // outcome of the last import, shown next to the buttons
+    Report(String),
+    Presets(Vec<String>),
+    // None if nothing came in before midi learn timed out
+    Learned(Option<Learned>),
+    // passthrough changed from outside the ui
+    Passthrough(bool),
 }
 
 pub fn connection_status(
@@ -44,11 +61,51 @@ pub fn connection_status(
                 match status {
                     Status::Connection(s) => {
                         if !s {
-                            let _ = device_tx.send_async(DeviceCmd::Update(DeviceUpdate::Clear));
+                            let _ = device_tx.send_async(DeviceCmd::Disconnected).await;
                         }
                         app_state.set_connected_to_server(s)
                     }
                     Status::Text(t) => app_state.set_server_name(SharedString::from(t)),
+                    Status::Restored(devices) => {
+                        let _ = device_tx.send_async(DeviceCmd::Reconcile(devices)).await;
+                    }
+                    Status::Report(r) => app_state.set_report(SharedString::from(r)),
+                    Status::Presets(names) => set_presets(&app, names),
+                    Status::Passthrough(p) => app_state.set_passthrough(p),
+                    Status::Learned(learned) => {
+                        app_state.set_learning(false);
+                        match learned {
+                            Some(l) => {
+                                let kind = match l.kind {
+                                    MidiKind::Cc => "cc",
+                                    MidiKind::Note => "note",
+                                };
+                                app_state.set_expose_cc(SharedString::from(l.number.to_string()));
+                                app_state.set_expose_channel(SharedString::from(
+                                    (l.channel + 1).to_string(),
+                                ));
+                                // a note is played on keys, starting at that note
+                                if l.kind == MidiKind::Note {
+                                    app_state.set_expose_ui_type(SharedString::from(
+                                        UIType::Keys.to_string(),
+                                    ));
+                                    let keys = UIParams::Keys {
+                                        base_note: l.number,
+                                        count: (128 - l.number as u16).min(12) as u8,
+                                    };
+                                    app_state
+                                        .set_expose_params(SharedString::from(keys.to_string()));
+                                }
+                                app_state.set_report(SharedString::from(format!(
+                                    "learned {kind} {} on channel {}",
+                                    l.number,
+                                    l.channel + 1
+                                )));
+                            }
+                            None => app_state
+                                .set_report(SharedString::from("midi learn: nothing received")),
+                        }
+                    }
                 }
             };
         }
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+// how often gliding values move, which also bounds how many messages a control sends
+pub const SLEW_TICK: Duration = Duration::from_millis(10);
+
+struct Glide {
+    current: f32,
+    target: u8,
+}
+
+// sits between incoming values and send_cc for the ccs that have a slew rate
+#[derive(Default)]
+pub struct Slew {
+    rates: HashMap<(u8, u8), f32>,
+    gliding: HashMap<(u8, u8), Glide>,
+    // what the synth last got, unknown values jump
+    sent: HashMap<(u8, u8), u8>,
+}
+
+impl Slew {
+    pub fn configure(&mut self, rates: Vec<((u8, u8), u16)>) {
+        self.rates = rates
+            .into_iter()
+            .filter(|(_, rate)| *rate > 0)
+            .map(|(key, rate)| (key, rate as f32))
+            .collect();
+        self.gliding.retain(|key, _| self.rates.contains_key(key));
+    }
+
+    // Some if the value should go out right away
+    pub fn signal(&mut self, channel: u8, cc: u8, value: u8) -> Option<u8> {
+        let key = (channel, cc);
+        match (self.rates.contains_key(&key), self.sent.get(&key)) {
+            (true, Some(&sent)) => {
+                let current = self
+                    .gliding
+                    .get(&key)
+                    .map_or(sent as f32, |glide| glide.current);
+                self.gliding.insert(
+                    key,
+                    Glide {
+                        current,
+                        target: value,
+                    },
+                );
+                None
+            }
+            _ => {
+                self.gliding.remove(&key);
+                self.sent.insert(key, value);
+                Some(value)
+            }
+        }
+    }
+
+    // the next step of every glide, (channel, cc, value)
+    pub fn tick(&mut self) -> Vec<(u8, u8, u8)> {
+        let dt = SLEW_TICK.as_secs_f32();
+        let mut out = vec![];
+        self.gliding.retain(|key, glide| {
+            let step = self.rates.get(key).copied().unwrap_or(f32::MAX) * dt;
+            let target = glide.target as f32;
+            glide.current = if (target - glide.current).abs() <= step {
+                target
+            } else {
+                glide.current + step.copysign(target - glide.current)
+            };
+            let value = glide.current.round() as u8;
+            if self.sent.get(key) != Some(&value) {
+                self.sent.insert(*key, value);
+                out.push((key.0, key.1, value));
+            }
+            glide.current != target
+        });
+        out
+    }
+
+    // after a panic nothing should keep gliding and the next values jump
+    pub fn reset(&mut self) {
+        self.gliding.clear();
+        self.sent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1000 per second is 10 per tick
+    fn slew() -> Slew {
+        let mut slew = Slew::default();
+        slew.configure(vec![((0, 7), 1000)]);
+        slew
+    }
+
+    #[test]
+    fn first_values_jump() {
+        let mut slew = slew();
+        assert_eq!(slew.signal(0, 7, 100), Some(100));
+        assert!(slew.tick().is_empty());
+        // ccs without a rate always jump
+        assert_eq!(slew.signal(0, 8, 50), Some(50));
+        assert_eq!(slew.signal(0, 8, 60), Some(60));
+    }
+
+    #[test]
+    fn ticks_glide_to_the_target_and_stop() {
+        let mut slew = slew();
+        slew.signal(0, 7, 0);
+        assert_eq!(slew.signal(0, 7, 25), None);
+        assert_eq!(slew.tick(), vec![(0, 7, 10)]);
+        assert_eq!(slew.tick(), vec![(0, 7, 20)]);
+        assert_eq!(slew.tick(), vec![(0, 7, 25)]);
+        assert!(slew.tick().is_empty());
+    }
+
+    #[test]
+    fn a_new_target_glides_on_from_where_it_is() {
+        let mut slew = slew();
+        slew.signal(0, 7, 100);
+        slew.signal(0, 7, 0);
+        assert_eq!(slew.tick(), vec![(0, 7, 90)]);
+        slew.signal(0, 7, 127);
+        assert_eq!(slew.tick(), vec![(0, 7, 100)]);
+    }
+
+    #[test]
+    fn reset_and_removed_rates_stop_glides() {
+        let mut slew = slew();
+        slew.signal(0, 7, 0);
+        slew.signal(0, 7, 127);
+        slew.reset();
+        assert!(slew.tick().is_empty());
+        assert_eq!(slew.signal(0, 7, 64), Some(64));
+
+        slew.signal(0, 7, 0);
+        slew.configure(vec![]);
+        assert!(slew.tick().is_empty());
+        assert_eq!(slew.signal(0, 7, 30), Some(30));
+    }
+}
@@ -1,8 +1,12 @@
 use flume::{Receiver, Sender};
 use tokio::runtime::Runtime;
-use util::{get_clipboard_content, Device, DeviceCmd, DeviceUpdate};
+use tracing::{info, info_span, warn, Instrument};
+use util::{
+    get_clipboard_content, Conflict, Device, DeviceCmd, DevicePatch, DeviceUpdate, MidiCmd,
+};
 
 use crate::exposed_state::ExposedState;
+use crate::setters::Status;
 
 pub fn device_task(
     rt: &Runtime,
@@ -10,8 +14,11 @@ pub fn device_task(
     command: Receiver<DeviceCmd>,
     mut state: ExposedState,
     slint_device_tx: Sender<Vec<Device>>,
+    status_tx: Sender<Status>,
+    midi_tx: Sender<MidiCmd>,
 ) {
     rt.spawn(async move {
+        let mut slews = vec![];
         loop {
             tokio::select! {
                 shutdown_option = shutdown.recv_async() => {
@@ -25,31 +32,139 @@ pub fn device_task(
                     if let Ok(e) = exposed_device_command {
 
                         match e {
-                            DeviceCmd::Login(login) => {state.login = Some(login);},
+                            DeviceCmd::Login(login) => {
+                                info!(url = %login.url, "device updates go to server");
+                                state.log_in(login, slint_device_tx.clone());
+                            },
                             DeviceCmd::CopyToClipboard => {let _ = &state.copy_to_clipboard();},
                             DeviceCmd::Paste => {
                                 if let Some(content) = get_clipboard_content() {
-                                    let _ = &state.paste(slint_device_tx.clone(), content).await;
+                                    let _ = &state.paste(slint_device_tx.clone(), status_tx.clone(), content)
+                                        .instrument(info_span!("paste"))
+                                        .await;
+                                } else {
+                                    warn!("clipboard is empty or unavailable");
+                                    let _ = status_tx
+                                        .send_async(Status::Report("clipboard is empty".to_string()))
+                                        .await;
                                 };
                             },
-                            DeviceCmd::Update(update) => {
-
-                                let update = match update {
-                                    DeviceUpdate::Remove(indexes) => {
-                                        DeviceUpdate::Remove(
-                                            indexes.into_iter()
-                                                .filter_map(|i| state.devices.get(i))
-                                                .map(|d| d.cc as usize).collect::<Vec<_>>())
-                                    },
-                                    DeviceUpdate::Clear => {
-                                        state.devices.clear();
-                                        DeviceUpdate::Clear
+                            DeviceCmd::Save(path) => {
+                                let _ = &state.save_file(&path, status_tx.clone())
+                                    .instrument(info_span!("save"))
+                                    .await;
+                            },
+                            DeviceCmd::Open(path) => {
+                                let _ = &state.open_file(&path, slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("open"))
+                                    .await;
+                            },
+                            DeviceCmd::SavePreset(name) => {
+                                let _ = &state.save_preset(&name, status_tx.clone())
+                                    .instrument(info_span!("preset"))
+                                    .await;
+                            },
+                            DeviceCmd::LoadPreset(name) => {
+                                let _ = &state.load_preset(&name, slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("preset"))
+                                    .await;
+                            },
+                            DeviceCmd::DeletePreset(name) => {
+                                let _ = &state.delete_preset(&name, status_tx.clone())
+                                    .instrument(info_span!("preset"))
+                                    .await;
+                            },
+                            DeviceCmd::ImportCcMap(path, instrument) => {
+                                let _ = &state.import_cc_map(&path, &instrument, slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("import"))
+                                    .await;
+                            },
+                            DeviceCmd::Reconcile(restored) => {
+                                let _ = &state.reconcile(restored, slint_device_tx.clone())
+                                    .instrument(info_span!("reconcile"))
+                                    .await;
+                            },
+                            DeviceCmd::Expose(device, edit, layout) => {
+                                let _ = &state.expose(device, edit, layout, slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("device_update"))
+                                    .await;
+                            },
+                            DeviceCmd::Move(index, by) => {
+                                let _ = &state.move_device(index, by, slint_device_tx.clone())
+                                    .instrument(info_span!("device_update"))
+                                    .await;
+                            },
+                            DeviceCmd::ToggleLock(index) => {
+                                if let Some(device) = state.devices.get(index) {
+                                    let update = DeviceUpdate::Modify(device.id, DevicePatch {
+                                        locked: Some(!device.locked),
+                                        ..Default::default()
+                                    });
+                                    let _ = &state.update_device(update, slint_device_tx.clone())
+                                        .instrument(info_span!("device_update"))
+                                        .await;
+                                }
+                            },
+                            DeviceCmd::Approve(id, client) => {
+                                match state.devices.iter().find(|d| d.id == id).map(|d| &d.conflict) {
+                                    Some(Conflict::Approved(clients)) if !clients.contains(&client) => {
+                                        let clients = clients.iter().cloned().chain([client]).collect();
+                                        let update = DeviceUpdate::Modify(id, DevicePatch {
+                                            conflict: Some(Conflict::Approved(clients)),
+                                            ..Default::default()
+                                        });
+                                        let _ = &state.update_device(update, slint_device_tx.clone())
+                                            .instrument(info_span!("device_update"))
+                                            .await;
                                     },
-                                    u => u,
-                                };
+                                    Some(Conflict::Approved(_)) => info!(id, %client, "already approved"),
+                                    _ => warn!(id, %client, "not a host approved device"),
+                                }
+                            },
+                            DeviceCmd::Hide(indexes) => {
+                                let update = DeviceUpdate::Remove(
+                                    indexes.into_iter()
+                                        .filter_map(|i| state.devices.get(i))
+                                        .map(|d| d.id)
+                                        .collect::<Vec<_>>());
 
-                                let _ = &state.update_device(update, slint_device_tx.clone()).await;
+                                let _ = &state.update_device(update, slint_device_tx.clone())
+                                    .instrument(info_span!("device_update"))
+                                    .await;
+                            },
+                            DeviceCmd::Update(update) => {
+                                let _ = &state.update_checked(update, slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("device_update"))
+                                    .await;
                             },
+                            DeviceCmd::Disconnected => {
+                                info!("connection lost, forgetting exposed devices");
+                                state.forget(slint_device_tx.clone());
+                            },
+                            DeviceCmd::Panic => {
+                                let _ = &state.panic(midi_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("panic"))
+                                    .await;
+                            },
+                            DeviceCmd::Undo => {
+                                let _ = &state.undo(slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("undo"))
+                                    .await;
+                            },
+                            DeviceCmd::Redo => {
+                                let _ = &state.redo(slint_device_tx.clone(), status_tx.clone())
+                                    .instrument(info_span!("redo"))
+                                    .await;
+                            },
+                        }
+
+                        // the midi side only hears about slew rates when they change
+                        let current = state.devices.iter()
+                            .flat_map(|d| d.slewed_ccs().into_iter().map(|key| (key, d.slew)))
+                            .collect::<Vec<_>>();
+                        if current != slews {
+                            slews = current;
+                            let _ = midi_tx.send_async(MidiCmd::Slew(slews.clone())).await;
                         }
                     }
                 }
@@ -1,28 +1,82 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use flume::Receiver;
-use tokio::{runtime::Runtime, sync::Mutex};
-use util::{Midi, MidiCmd};
+use flume::{Receiver, Sender};
+use tokio::{
+    runtime::Runtime,
+    sync::Mutex,
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{info, trace_span};
+use util::{learn, Midi, MidiCmd};
+
+use crate::setters::Status;
+use crate::slew::{Slew, SLEW_TICK};
+
+const LEARN_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn midi_task(
     rt: &Runtime,
     shutdown: Receiver<bool>,
     midi: Arc<Mutex<Midi>>,
     midi_rx: Receiver<MidiCmd>,
+    status_tx: Sender<Status>,
 ) {
     rt.spawn(async move {
+        let mut slew = Slew::default();
+        let mut tick = interval(SLEW_TICK);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
         loop {
             tokio::select! {
                 command_option = midi_rx.recv_async() => {
                  if let Ok(command) = command_option {
                      let mut midi = midi.lock().await;
                      match command {
-                         MidiCmd::Dummy(cc) => midi.send_cc(cc, 0),
-                         MidiCmd::Signal(cc, value) => midi.send_cc(cc, value),
+                         MidiCmd::Dummy(cc) => {
+                             info!(cc, "sending dummy cc");
+                             midi.send_cc(0, cc, 0)
+                         },
+                         MidiCmd::Signal(channel, cc, value) => {
+                             if let Some(value) = slew.signal(channel, cc, value) {
+                                 midi.send_cc(channel, cc, value)
+                             }
+                         },
+                         MidiCmd::Note(channel, note, velocity) => {
+                             midi.send_note(channel, note, velocity)
+                         },
                          MidiCmd::Port(port) => midi.update_port(port),
+                         MidiCmd::Panic(channels) => {
+                             info!(?channels, "panic");
+                             slew.reset();
+                             midi.panic(&channels)
+                         },
+                         MidiCmd::Slew(rates) => {
+                             info!(?rates, "slew rates");
+                             slew.configure(rates)
+                         },
+                         // listening blocks, so it happens off the runtime and without the output lock
+                         MidiCmd::Learn => {
+                             info!("midi learn started");
+                             let status_tx = status_tx.clone();
+                             tokio::task::spawn_blocking(move || {
+                                 let learned = learn(LEARN_TIMEOUT);
+                                 info!(?learned, "midi learn done");
+                                 let _ = status_tx.send(Status::Learned(learned));
+                             });
+                         },
                      }
                     }
                 }
+                _ = tick.tick() => {
+                    let steps = slew.tick();
+                    if !steps.is_empty() {
+                        let mut midi = midi.lock().await;
+                        for (channel, cc, value) in steps {
+                            let _span = trace_span!("slew").entered();
+                            midi.send_cc(channel, cc, value)
+                        }
+                    }
+                }
                 shutdown_option = shutdown.recv_async() => {
                     if let Ok(shutdown) = shutdown_option {
                         if shutdown {
@@ -1,13 +1,18 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use flume::{Receiver, Sender};
 use futures_util::stream::StreamExt;
+use reqwest::Url;
 use tokio::{runtime::Runtime, sync::Mutex};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
-use util::MidiCmd;
+use tracing::{error, info, instrument, trace, warn};
+use util::{HostFrame, MidiCmd, MidiKind, ServerMessage};
 
+use crate::activity::Activity;
 use crate::{Login, Status};
 
+#[allow(clippy::too_many_arguments)]
 pub fn setup_task(
     rt: &Runtime,
     shutdown_rx: Receiver<bool>,
@@ -17,6 +22,7 @@ pub fn setup_task(
     status_tx: Sender<Status>,
     passthrough: Arc<Mutex<bool>>,
     logout: Receiver<()>,
+    activity_tx: Sender<Activity>,
 ) {
     rt.spawn(async move {
         loop {
@@ -37,6 +43,7 @@ pub fn setup_task(
                             status_tx.clone(),
                             passthrough.clone(),
                             logout.clone(),
+                            activity_tx.clone(),
                         ).await.is_err() {
                             let _ = login_tx.send(false);
                         };
@@ -47,6 +54,7 @@ pub fn setup_task(
     });
 }
 
+#[instrument(skip_all, fields(url = %login.url))]
 async fn setup_connection(
     login: Login,
     login_tx: Sender<bool>,
@@ -54,40 +62,100 @@ async fn setup_connection(
     status_tx: Sender<Status>,
     passthrough: Arc<Mutex<bool>>,
     logout: Receiver<()>,
+    activity_tx: Sender<Activity>,
 ) -> Result<(), ()> {
-    let ws_url = format!("ws://{}/login?password={}", login.url, login.pass);
+    let mut ws_url = match Url::parse(&format!("ws://{}/login", login.url)) {
+        Ok(url) => url,
+        Err(e) => {
+            let _ = login_tx.send(false);
+            error!("invalid server url: {e}");
+            return Ok(());
+        }
+    };
+    ws_url
+        .query_pairs_mut()
+        .append_pair("password", &login.pass)
+        .extend_pairs(login.session.iter().map(|s| ("session", s)));
 
-    match connect_async(ws_url).await {
+    match connect_async(ws_url.as_str()).await {
         Ok((mut ws_stream, _)) => {
+            info!("connected to server");
             let _ = login_tx.send(true);
             let _ = status_tx.send(Status::Connection(true));
 
+            // a logout requested while there was no connection must not end this one
+            while logout.try_recv().is_ok() {}
+
             loop {
                 tokio::select! {
                     message = ws_stream.next() => {
-                        if let Some(message) = message {
-                            match message {
-                                Ok(message) => match message {
-                                    Message::Text(text) => {
-                                        let _ = status_tx.send_async(Status::Text(text)).await;
-                                    }
-                                    _ => {
-                                        if *passthrough.lock().await {
-                                            if let Some((cc, value)) = parse_message(message.into_data()) {
-                                                let _ = midi_tx.send_async(MidiCmd::Signal(cc, value)).await;
-                                            };
-                                        }
-                                    }
-                                },
-                                Err(_) => {
-                                    let _ = status_tx.send_async(Status::Connection(false)).await;
+                        match message {
+                            Some(Ok(Message::Text(text))) => match ServerMessage::from_json(&text) {
+                                Some(ServerMessage::Name(name)) => {
+                                    info!(server_name = %name, "server identified");
+                                    let _ = status_tx.send_async(Status::Text(name)).await;
+                                }
+                                Some(ServerMessage::Devices(devices)) => {
+                                    info!(count = devices.len(), "server restored exposed devices");
+                                    let _ = status_tx.send_async(Status::Restored(devices)).await;
+                                }
+                                Some(ServerMessage::ShuttingDown) => {
+                                    warn!("server is shutting down");
+                                }
+                                Some(ServerMessage::UserJoined(user, name)) => {
+                                    info!(user, %name, "user joined");
+                                    let _ = activity_tx.try_send(Activity::Joined(user, name));
                                 }
+                                Some(ServerMessage::UserLeft(user)) => {
+                                    info!(user, "user left");
+                                    let _ = activity_tx.try_send(Activity::Left(user));
+                                }
+                                Some(ServerMessage::Requested(user, client, device)) => {
+                                    info!(user, %client, device, "user asks for a host approved device");
+                                    let _ = activity_tx.try_send(Activity::Requested(user, client, device));
+                                }
+                                // only meant for users
+                                Some(ServerMessage::Reset(_)) => {}
+                                None => warn!(%text, "unknown message from server"),
+                            },
+                            Some(Ok(Message::Close(frame))) => {
+                                info!(?frame, "server closed the connection");
+                                let _ = status_tx.send_async(Status::Connection(false)).await;
+                                break;
+                            }
+                            Some(Ok(message)) => {
+                                if let Some(frame) = HostFrame::from_bytes(&message.into_data()) {
+                                    trace!(?frame, "received value");
+                                    // the monitor shows values even with passthrough off and never holds up midi
+                                    let _ = activity_tx.try_send(Activity::Value(frame, SystemTime::now()));
+                                    if *passthrough.lock().await {
+                                        let command = match frame.kind {
+                                            MidiKind::Cc => MidiCmd::Signal(frame.channel, frame.number, frame.value),
+                                            MidiKind::Note => MidiCmd::Note(frame.channel, frame.number, frame.value),
+                                        };
+                                        let _ = midi_tx.send_async(command).await;
+                                    }
+                                };
+                            }
+                            Some(Err(e)) => {
+                                warn!("connection error: {e}");
+                                let _ = status_tx.send_async(Status::Connection(false)).await;
+                                break;
+                            }
+                            None => {
+                                warn!("connection ended");
+                                let _ = status_tx.send_async(Status::Connection(false)).await;
+                                break;
                             }
                         }
                     }
 
                     _ = logout.recv_async() => {
-                        let _ = ws_stream.close(None).await;
+                        info!("logging out");
+                        if let Err(e) = ws_stream.close(None).await {
+                            warn!("failed to close connection: {e}");
+                        }
+                        let _ = status_tx.send_async(Status::Connection(false)).await;
                         break;
                     }
                 }
@@ -95,14 +163,8 @@ async fn setup_connection(
         }
         Err(e) => {
             let _ = login_tx.send(false);
-            eprintln!("{e}");
+            error!("failed to connect: {e}");
         }
     };
     Ok(())
 }
-
-fn parse_message(data: Vec<u8>) -> Option<(u8, u8)> {
-    let cc = data.first()?;
-    let value = data.get(1)?;
-    Some((*cc, *value))
-}
@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use util::{Conflict, DeviceId, HostFrame, UserId};
+
+use crate::limits::env_or;
+
+// a single output of a device, see Device::outputs
+pub type Slot = (DeviceId, u8);
+
+pub enum Push {
+    Queued,
+    // a frame that was still waiting got replaced
+    Replaced,
+    // someone else holds the slot, or the user isn't approved
+    Refused,
+    // the first refusal of a user on a host approved device
+    Unapproved,
+}
+
+#[derive(Default)]
+struct Pending {
+    frames: HashMap<Slot, HostFrame>,
+    events: Vec<HostFrame>,
+    // who holds a slot under Conflict::Hold and since when they last moved it
+    holds: HashMap<Slot, (UserId, Instant)>,
+    // the latest value of every user under Conflict::Average
+    recent: HashMap<Slot, HashMap<UserId, (u8, Instant)>>,
+    // refusals the host has been told about
+    requested: HashSet<(UserId, DeviceId)>,
+}
+
+// keeps only the latest frame per slot until the host side flushes it, the device's
+// conflict policy decides what that frame is when several users move it. events
+// (presses and notes) are all kept, in order
+pub struct Bridge {
+    pending: Mutex<Pending>,
+    pub flush_interval: Duration,
+    average_window: Duration,
+}
+
+impl Bridge {
+    pub fn from_env() -> Self {
+        let flush_rate: u64 = env_or("BRIDGE_FLUSH_RATE", 100);
+        Bridge {
+            pending: Mutex::new(Pending::default()),
+            flush_interval: Duration::from_micros(1_000_000 / flush_rate.max(1)),
+            average_window: Duration::from_millis(env_or("AVERAGE_WINDOW_MS", 1000)),
+        }
+    }
+
+    pub async fn push(
+        &self,
+        user: UserId,
+        client: &str,
+        slot: Slot,
+        frame: HostFrame,
+        conflict: &Conflict,
+        event: bool,
+    ) -> Push {
+        let mut pending = self.pending.lock().await;
+        let now = Instant::now();
+        let frame = match conflict {
+            Conflict::LastWriter => frame,
+            Conflict::Hold(secs) => {
+                let hold = Duration::from_secs(*secs as u64);
+                match pending.holds.get(&slot) {
+                    Some(&(holder, since))
+                        if holder != user && now.duration_since(since) < hold =>
+                    {
+                        return Push::Refused;
+                    }
+                    _ => {
+                        pending.holds.insert(slot, (user, now));
+                        frame
+                    }
+                }
+            }
+            Conflict::Average => {
+                let recent = pending.recent.entry(slot).or_default();
+                recent.insert(user, (frame.value, now));
+                // the value just pushed always counts, however short the window
+                recent.retain(|u, (_, at)| {
+                    *u == user || now.duration_since(*at) < self.average_window
+                });
+                let sum = recent.values().map(|(v, _)| *v as u32).sum::<u32>();
+                HostFrame {
+                    value: (sum as f32 / recent.len() as f32).round() as u8,
+                    ..frame
+                }
+            }
+            Conflict::Approved(clients) => {
+                if !clients.iter().any(|c| c == client) {
+                    return match pending.requested.insert((user, slot.0)) {
+                        true => Push::Unapproved,
+                        false => Push::Refused,
+                    };
+                }
+                frame
+            }
+        };
+        if event {
+            pending.events.push(frame);
+            return Push::Queued;
+        }
+        match pending.frames.insert(slot, frame) {
+            Some(_) => Push::Replaced,
+            None => Push::Queued,
+        }
+    }
+
+    // a user that goes away lets go of everything it held
+    pub async fn leave(&self, user: UserId) {
+        let mut pending = self.pending.lock().await;
+        pending.holds.retain(|_, (holder, _)| *holder != user);
+        pending.recent.values_mut().for_each(|r| {
+            r.remove(&user);
+        });
+        pending.requested.retain(|(s, _)| *s != user);
+    }
+
+    pub async fn depth(&self) -> usize {
+        let pending = self.pending.lock().await;
+        pending.frames.len() + pending.events.len()
+    }
+
+    pub async fn drain(&self) -> Vec<HostFrame> {
+        let mut pending = self.pending.lock().await;
+        let mut frames = std::mem::take(&mut pending.events);
+        frames.extend(pending.frames.drain().map(|(_, frame)| frame));
+        frames
+    }
+
+    pub async fn clear(&self) {
+        let mut pending = self.pending.lock().await;
+        pending.frames.clear();
+        pending.events.clear();
+        pending.holds.clear();
+        pending.recent.clear();
+        pending.requested.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bridge() -> Bridge {
+        Bridge {
+            pending: Mutex::new(Pending::default()),
+            flush_interval: Duration::from_millis(10),
+            average_window: Duration::from_secs(60),
+        }
+    }
+
+    fn cc(value: u8) -> HostFrame {
+        HostFrame::cc(1, 0, 7, value)
+    }
+
+    fn values(frames: Vec<HostFrame>) -> Vec<u8> {
+        frames.iter().map(|f| f.value).collect()
+    }
+
+    #[tokio::test]
+    async fn positions_keep_the_latest_value() {
+        let bridge = bridge();
+        let last = Conflict::LastWriter;
+        assert!(matches!(
+            bridge.push(0, "c0", (1, 0), cc(1), &last, false).await,
+            Push::Queued
+        ));
+        assert!(matches!(
+            bridge.push(1, "c1", (1, 0), cc(2), &last, false).await,
+            Push::Replaced
+        ));
+        assert_eq!(values(bridge.drain().await), vec![2]);
+        assert!(bridge.drain().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn events_are_all_kept_in_order() {
+        let bridge = bridge();
+        let last = Conflict::LastWriter;
+        bridge.push(0, "c0", (1, 0), cc(127), &last, true).await;
+        bridge.push(0, "c0", (1, 0), cc(0), &last, true).await;
+        bridge.push(0, "c0", (1, 0), cc(127), &last, true).await;
+        assert_eq!(values(bridge.drain().await), vec![127, 0, 127]);
+    }
+
+    #[tokio::test]
+    async fn holders_keep_the_slot() {
+        let bridge = bridge();
+        let hold = Conflict::Hold(60);
+        bridge.push(0, "c0", (1, 0), cc(1), &hold, false).await;
+        assert!(matches!(
+            bridge.push(1, "c1", (1, 0), cc(2), &hold, false).await,
+            Push::Refused
+        ));
+        bridge.push(0, "c0", (1, 0), cc(3), &hold, false).await;
+        assert_eq!(values(bridge.drain().await), vec![3]);
+        // until they leave
+        bridge.leave(0).await;
+        bridge.push(1, "c1", (1, 0), cc(4), &hold, false).await;
+        assert_eq!(values(bridge.drain().await), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn holds_run_out() {
+        let bridge = bridge();
+        let hold = Conflict::Hold(0);
+        bridge.push(0, "c0", (1, 0), cc(1), &hold, false).await;
+        assert!(matches!(
+            bridge.push(1, "c1", (1, 0), cc(2), &hold, false).await,
+            Push::Replaced
+        ));
+    }
+
+    #[tokio::test]
+    async fn average_of_recent_users() {
+        let bridge = bridge();
+        let average = Conflict::Average;
+        bridge.push(0, "c0", (1, 0), cc(100), &average, false).await;
+        bridge.push(1, "c1", (1, 0), cc(0), &average, false).await;
+        assert_eq!(values(bridge.drain().await), vec![50]);
+        bridge.leave(1).await;
+        bridge.push(0, "c0", (1, 0), cc(90), &average, false).await;
+        assert_eq!(values(bridge.drain().await), vec![90]);
+    }
+
+    #[tokio::test]
+    async fn unapproved_users_are_reported_once() {
+        let bridge = bridge();
+        let approved = Conflict::Approved(vec!["c1".to_string()]);
+        assert!(matches!(
+            bridge.push(0, "c0", (1, 0), cc(1), &approved, false).await,
+            Push::Unapproved
+        ));
+        assert!(matches!(
+            bridge.push(0, "c0", (1, 0), cc(2), &approved, false).await,
+            Push::Refused
+        ));
+        assert!(matches!(
+            bridge.push(1, "c1", (1, 0), cc(3), &approved, false).await,
+            Push::Queued
+        ));
+        assert_eq!(values(bridge.drain().await), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn approvals_follow_the_client_not_the_user() {
+        let bridge = bridge();
+        let approved = Conflict::Approved(vec!["c1".to_string()]);
+        // a reload comes back with a new user id but the same client
+        assert!(matches!(
+            bridge.push(7, "c1", (1, 0), cc(1), &approved, false).await,
+            Push::Queued
+        ));
+        // and a wrapped id that once was approved doesn't get through as someone else
+        assert!(matches!(
+            bridge.push(1, "c2", (1, 0), cc(2), &approved, false).await,
+            Push::Unapproved
+        ));
+        assert_eq!(values(bridge.drain().await), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn a_zero_window_averages_only_the_pusher() {
+        let bridge = Bridge {
+            average_window: Duration::ZERO,
+            ..bridge()
+        };
+        let average = Conflict::Average;
+        bridge.push(0, "c0", (1, 0), cc(100), &average, false).await;
+        bridge.push(1, "c1", (1, 0), cc(60), &average, false).await;
+        assert_eq!(values(bridge.drain().await), vec![60]);
+    }
+
+    #[tokio::test]
+    async fn clearing_forgets_reported_requests() {
+        let bridge = bridge();
+        let approved = Conflict::Approved(vec![]);
+        bridge.push(0, "c0", (1, 0), cc(1), &approved, false).await;
+        bridge.clear().await;
+        assert!(matches!(
+            bridge.push(0, "c0", (1, 0), cc(2), &approved, false).await,
+            Push::Unapproved
+        ));
+    }
+}
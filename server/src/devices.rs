@@ -0,0 +1,246 @@
+use tokio::sync::watch;
+use tracing::warn;
+use util::{arrange, Device, DeviceId, DeviceParseError, DeviceUpdate};
+
+// the exposed devices in the order the host put them
+pub struct ExposedDevices {
+    devices: Vec<Device>,
+    next_id: DeviceId,
+    // what users get to see, sorted into pages and groups
+    arranged: watch::Sender<Vec<Device>>,
+}
+
+impl ExposedDevices {
+    pub fn new() -> Self {
+        ExposedDevices {
+            devices: vec![],
+            next_id: 1,
+            arranged: watch::channel(vec![]).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Vec<Device>> {
+        self.arranged.subscribe()
+    }
+
+    fn publish(&self) {
+        let arranged = arrange(&self.devices)
+            .into_iter()
+            .map(|i| self.devices[i].clone())
+            .collect();
+        self.arranged.send_replace(arranged);
+    }
+
+    pub fn list(&self) -> Vec<Device> {
+        self.devices.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn get(&self, id: DeviceId) -> Option<&Device> {
+        self.devices.iter().find(|d| d.id == id)
+    }
+
+    // exposed and not locked by the host
+    pub fn accepts(&self, id: DeviceId) -> bool {
+        self.get(id).is_some_and(|d| !d.locked)
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+        self.publish();
+    }
+
+    // devices without an id get a fresh one. ids count up and only come round again
+    // once all of them were used, so users holding on to a hidden device are unlikely
+    // to end up moving another one
+    pub fn insert(&mut self, mut device: Device) {
+        if device.id == 0 {
+            let Some(id) = self.free_id() else {
+                warn!(description = %device.description, "no device ids left, not exposing");
+                return;
+            };
+            device.id = id;
+            self.next_id = id.wrapping_add(1).max(1);
+        } else if device.id >= self.next_id {
+            self.next_id = device.id.wrapping_add(1).max(1);
+        }
+        // e.g. layout files that leave params out
+        if !device.params.fits(device.ui_type) {
+            device.params = device.ui_type.default_params(device.cc);
+        }
+
+        match self.devices.iter_mut().find(|d| d.id == device.id) {
+            Some(existing) => *existing = device,
+            None => self.devices.push(device),
+        }
+    }
+
+    // the first unused id from next_id on, wrapping past the end and skipping 0
+    fn free_id(&self) -> Option<DeviceId> {
+        (self.next_id..=DeviceId::MAX)
+            .chain(1..self.next_id)
+            .find(|id| self.get(*id).is_none())
+    }
+
+    // the same checks the host runs, a modify on the device it ends up as
+    pub fn check(&self, update: &DeviceUpdate) -> Result<(), DeviceParseError> {
+        update.validate()?;
+        match update {
+            DeviceUpdate::Modify(id, patch) => match self.get(*id) {
+                Some(existing) => {
+                    let mut edited = existing.clone();
+                    edited.apply(patch.clone());
+                    edited.validate()
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&mut self, update: DeviceUpdate) {
+        match update {
+            DeviceUpdate::Add(additions) => additions.into_iter().for_each(|a| self.insert(a)),
+            DeviceUpdate::Remove(removals) => self.devices.retain(|d| !removals.contains(&d.id)),
+            DeviceUpdate::Modify(id, patch) => {
+                if let Some(device) = self.devices.iter_mut().find(|d| d.id == id) {
+                    device.apply(patch);
+                }
+            }
+            DeviceUpdate::Replace(devices) => {
+                self.devices.clear();
+                devices.into_iter().for_each(|d| self.insert(d));
+            }
+            DeviceUpdate::Reorder(order) => {
+                let position = |d: &Device| {
+                    order
+                        .iter()
+                        .position(|id| *id == d.id)
+                        .unwrap_or(order.len())
+                };
+                self.devices.sort_by_key(position);
+            }
+            DeviceUpdate::Clear => self.devices.clear(),
+        }
+        self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::{DevicePatch, UIType};
+
+    fn slide(id: DeviceId) -> Device {
+        Device {
+            id,
+            ..Device::new(1, UIType::Slide, "slide".to_string())
+        }
+    }
+
+    #[test]
+    fn new_devices_get_fresh_ids() {
+        let mut devices = ExposedDevices::new();
+        devices.insert(slide(0));
+        devices.insert(slide(0));
+        let ids = devices.list().iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn ids_wrap_around_to_free_ones() {
+        let mut devices = ExposedDevices::new();
+        devices.insert(slide(1));
+        devices.insert(slide(DeviceId::MAX));
+        devices.insert(slide(0));
+        devices.insert(slide(0));
+        let ids = devices.list().iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, DeviceId::MAX, 2, 3]);
+    }
+
+    fn ids(devices: &ExposedDevices) -> Vec<DeviceId> {
+        devices.list().iter().map(|d| d.id).collect()
+    }
+
+    fn three() -> ExposedDevices {
+        let mut devices = ExposedDevices::new();
+        devices.apply(DeviceUpdate::Add(vec![slide(0), slide(0), slide(0)]));
+        devices
+    }
+
+    #[test]
+    fn modify_patches_only_the_named_device() {
+        let mut devices = three();
+        let patch = DevicePatch {
+            description: Some("cutoff".to_string()),
+            max: Some(100),
+            ..Default::default()
+        };
+        devices.apply(DeviceUpdate::Modify(2, patch.clone()));
+        let edited = devices.get(2).unwrap();
+        assert_eq!((edited.description.as_str(), edited.max), ("cutoff", 100));
+        assert_eq!(devices.get(1).unwrap().description, "slide");
+        // unknown ids change nothing
+        devices.apply(DeviceUpdate::Modify(9, patch));
+        assert_eq!(ids(&devices), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn replace_keeps_given_ids_and_numbers_new_ones() {
+        let mut devices = three();
+        devices.apply(DeviceUpdate::Replace(vec![slide(3), slide(0)]));
+        assert_eq!(ids(&devices), vec![3, 4]);
+    }
+
+    #[test]
+    fn reorder_puts_unlisted_devices_last() {
+        let mut devices = three();
+        devices.apply(DeviceUpdate::Reorder(vec![3, 1]));
+        assert_eq!(ids(&devices), vec![3, 1, 2]);
+        // and it reaches users arranged the same way
+        let arranged = devices
+            .subscribe()
+            .borrow()
+            .iter()
+            .map(|d| d.id)
+            .collect::<Vec<_>>();
+        assert_eq!(arranged, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn checks_reject_what_the_host_would() {
+        let devices = three();
+        let bad_cc = Device {
+            cc: 200,
+            ..slide(0)
+        };
+        assert!(devices
+            .check(&DeviceUpdate::Add(vec![bad_cc.clone()]))
+            .is_err());
+        assert!(devices.check(&DeviceUpdate::Replace(vec![bad_cc])).is_err());
+        let narrower = DevicePatch {
+            max: Some(100),
+            ..Default::default()
+        };
+        assert!(devices.check(&DeviceUpdate::Modify(1, narrower)).is_ok());
+        // fine on its own, but the device's default of 0 ends up below it
+        let above_default = DevicePatch {
+            min: Some(100),
+            ..Default::default()
+        };
+        assert!(above_default.validate().is_ok());
+        assert!(devices
+            .check(&DeviceUpdate::Modify(1, above_default))
+            .is_err());
+        let reversed = DevicePatch {
+            min: Some(100),
+            max: Some(20),
+            ..Default::default()
+        };
+        assert!(devices.check(&DeviceUpdate::Modify(9, reversed)).is_err());
+        assert!(devices.check(&DeviceUpdate::Reorder(vec![3, 2, 1])).is_ok());
+    }
+}
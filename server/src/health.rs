@@ -0,0 +1,36 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{AppState, BUILD_DIR};
+
+async fn report(state: &AppState, status: &str) -> Value {
+    json!({
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.started.elapsed().as_secs(),
+        "host_connected": *state.connected.lock().await,
+        "exposed_devices": state.exposed_devices.lock().await.len(),
+    })
+}
+
+pub async fn healthz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(report(&state, "ok").await))
+}
+
+// ready once the user frontend can be served, a missing host is reported but
+// doesn't make the server unready since the host has to reach it to log in
+pub async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if Path::new(BUILD_DIR).join("index.html").exists() {
+        (StatusCode::OK, Json(report(&state, "ready").await))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(report(&state, "frontend build missing").await),
+        )
+    }
+}
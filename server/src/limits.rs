@@ -0,0 +1,255 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use util::HostFrame;
+
+use crate::bridge::Slot;
+
+pub struct Limits {
+    pub max_users: usize,
+    pub user_rate: u32,
+    pub control_rate: u32,
+    // how often values held back by the limits are retried
+    pub drain_interval: Duration,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        Limits {
+            max_users: env_or("MAX_USERS", 32),
+            user_rate: env_or("USER_RATE_LIMIT", 60),
+            control_rate: env_or("CONTROL_RATE_LIMIT", 30),
+            drain_interval: Duration::from_millis(env_or::<u64>("LIMIT_DRAIN_MS", 20).max(1)),
+        }
+    }
+}
+
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+// token bucket refilled continuously, allowing bursts of up to one second worth of messages
+struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        RateLimiter {
+            rate: per_second.max(1) as f64,
+            tokens: per_second.max(1) as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens =
+            (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+    }
+
+    fn ready(&mut self) -> bool {
+        self.refill();
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+pub enum Admission {
+    Forward,
+    Deferred,
+    Coalesced,
+    // too many events waiting already
+    Overflow,
+}
+
+// events are held back in order rather than coalesced, up to this many per user
+const MAX_EVENTS: usize = 64;
+
+pub struct UserLimiter {
+    user: RateLimiter,
+    controls: HashMap<Slot, RateLimiter>,
+    control_rate: u32,
+    pending: HashMap<Slot, HostFrame>,
+    events: VecDeque<(Slot, HostFrame)>,
+}
+
+impl UserLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        UserLimiter {
+            user: RateLimiter::new(limits.user_rate),
+            controls: HashMap::new(),
+            control_rate: limits.control_rate,
+            pending: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    // frames over the limit wait for the next drain, replacing
+    // whatever was already waiting for the same slot
+    pub fn admit(&mut self, slot: Slot, frame: HostFrame) -> Admission {
+        if self.acquire(slot) {
+            self.pending.remove(&slot);
+            Admission::Forward
+        } else if self.pending.insert(slot, frame).is_some() {
+            Admission::Coalesced
+        } else {
+            Admission::Deferred
+        }
+    }
+
+    // presses and notes, each of them has to arrive and in the order they were sent
+    pub fn admit_event(&mut self, slot: Slot, frame: HostFrame) -> Admission {
+        if self.events.is_empty() && self.acquire(slot) {
+            Admission::Forward
+        } else if self.events.len() >= MAX_EVENTS {
+            Admission::Overflow
+        } else {
+            self.events.push_back((slot, frame));
+            Admission::Deferred
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<(Slot, HostFrame)> {
+        let mut events = vec![];
+        while let Some(&(slot, _)) = self.events.front() {
+            if !self.acquire(slot) {
+                break;
+            }
+            events.extend(self.events.pop_front());
+        }
+
+        let ready = self
+            .pending
+            .keys()
+            .copied()
+            .collect::<Vec<Slot>>()
+            .into_iter()
+            .filter(|slot| self.acquire(*slot))
+            .collect::<Vec<Slot>>();
+
+        events
+            .into_iter()
+            .chain(
+                ready
+                    .into_iter()
+                    .filter_map(|slot| self.pending.remove(&slot).map(|frame| (slot, frame))),
+            )
+            .collect()
+    }
+
+    fn acquire(&mut self, slot: Slot) -> bool {
+        let control_rate = self.control_rate;
+        let control = self
+            .controls
+            .entry(slot)
+            .or_insert_with(|| RateLimiter::new(control_rate));
+
+        if self.user.ready() && control.ready() {
+            self.user.take();
+            control.take();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(user_rate: u32, control_rate: u32) -> Limits {
+        Limits {
+            max_users: 1,
+            user_rate,
+            control_rate,
+            drain_interval: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn rate_limiter_allows_a_second_worth_of_burst() {
+        let mut limiter = RateLimiter::new(3);
+        for _ in 0..3 {
+            assert!(limiter.ready());
+            limiter.take();
+        }
+        assert!(!limiter.ready());
+    }
+
+    #[test]
+    fn zero_rate_still_lets_one_through() {
+        let mut limiter = RateLimiter::new(0);
+        assert!(limiter.ready());
+        limiter.take();
+        assert!(!limiter.ready());
+    }
+
+    #[test]
+    fn frames_over_the_limit_are_deferred_then_coalesced() {
+        let mut limiter = UserLimiter::new(&limits(100, 1));
+        let frame = |value| HostFrame::cc(1, 0, 7, value);
+        assert!(matches!(
+            limiter.admit((1, 0), frame(1)),
+            Admission::Forward
+        ));
+        assert!(matches!(
+            limiter.admit((1, 0), frame(2)),
+            Admission::Deferred
+        ));
+        assert!(matches!(
+            limiter.admit((1, 0), frame(3)),
+            Admission::Coalesced
+        ));
+        // other slots have their own budget
+        assert!(matches!(
+            limiter.admit((2, 0), frame(4)),
+            Admission::Forward
+        ));
+        assert!(limiter.drain().is_empty());
+    }
+
+    #[test]
+    fn events_wait_in_order_instead_of_coalescing() {
+        let mut limiter = UserLimiter::new(&limits(100, 1));
+        let frame = |value| HostFrame::cc(1, 0, 7, value);
+        assert!(matches!(
+            limiter.admit_event((1, 0), frame(127)),
+            Admission::Forward
+        ));
+        assert!(matches!(
+            limiter.admit_event((1, 0), frame(0)),
+            Admission::Deferred
+        ));
+        assert!(matches!(
+            limiter.admit_event((1, 0), frame(127)),
+            Admission::Deferred
+        ));
+        for _ in 0..MAX_EVENTS - 2 {
+            limiter.admit_event((1, 0), frame(0));
+        }
+        assert!(matches!(
+            limiter.admit_event((1, 0), frame(0)),
+            Admission::Overflow
+        ));
+    }
+
+    #[test]
+    fn the_user_limit_covers_all_slots() {
+        let mut limiter = UserLimiter::new(&limits(1, 100));
+        let frame = HostFrame::cc(1, 0, 7, 0);
+        assert!(matches!(limiter.admit((1, 0), frame), Admission::Forward));
+        assert!(matches!(limiter.admit((2, 0), frame), Admission::Deferred));
+    }
+}
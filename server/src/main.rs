@@ -1,6 +1,14 @@
-use axum::extract::ws::Message;
-use axum::extract::{Query, State};
-use axum::http::{StatusCode, Uri};
+mod bridge;
+mod devices;
+mod health;
+mod limits;
+mod metrics;
+mod store;
+mod users;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{header, StatusCode, Uri};
 use axum::routing::{any_service, MethodRouter};
 use axum::Json;
 use axum::{
@@ -9,82 +17,176 @@ use axum::{
     routing::{get, post},
     Router,
 };
+use bridge::{Bridge, Push, Slot};
+use devices::ExposedDevices;
 use dotenv::dotenv;
-use flume::{bounded, Receiver, Sender};
+use health::{healthz, readyz};
+use limits::{Admission, Limits, UserLimiter};
+use metrics::Metrics;
 use serde::Deserialize;
 use serde_json::json;
-use std::collections::HashMap;
 use std::env;
 use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use store::Store;
+use tokio::signal;
+use tokio::sync::{broadcast, watch, Mutex};
 use tower_http::services::ServeDir;
-use util::{Device, DeviceUpdate};
+use tracing::{debug, info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use users::Users;
+use util::{client_id, DeviceUpdate, HostFrame, ServerMessage, UserFrame, UserId};
 
 struct AppState {
     connected: Mutex<bool>,
-    exposed_devices: Mutex<HashMap<u8, Device>>,
+    exposed_devices: Mutex<ExposedDevices>,
     password: String,
     server_name: String,
-    bridge_tx: Sender<Message>,
-    bridge_rx: Receiver<Message>,
+    bridge: Bridge,
+    limits: Limits,
+    metrics: Metrics,
+    started: Instant,
+    shutdown: watch::Sender<bool>,
+    store: Store,
+    host_session: Mutex<String>,
+    users: Mutex<Users>,
+    host_messages: broadcast::Sender<ServerMessage>,
+    user_messages: broadcast::Sender<ServerMessage>,
 }
 
+const DEFAULT_SESSION: &str = "default";
+
+const BUILD_DIR: &str = "server/build";
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-
-    let (bridge_tx, bridge_rx): (Sender<Message>, Receiver<Message>) = bounded(10);
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
 
     let shared_state = Arc::new(AppState {
         connected: Mutex::new(false),
-        exposed_devices: Mutex::new(HashMap::new()),
+        exposed_devices: Mutex::new(ExposedDevices::new()),
         password: env::var("WS_PASSWORD").expect("WS_PASSWORD must be set"),
         server_name: env::var("SERVER_NAME").expect("SERVER_NAME must be set"),
-        bridge_tx,
-        bridge_rx,
+        bridge: Bridge::from_env(),
+        limits: Limits::from_env(),
+        metrics: Metrics::default(),
+        started: Instant::now(),
+        shutdown: watch::channel(false).0,
+        store: Store::from_env(),
+        host_session: Mutex::new(DEFAULT_SESSION.to_string()),
+        users: Mutex::new(Users::new()),
+        host_messages: broadcast::channel(64).0,
+        user_messages: broadcast::channel(64).0,
     });
 
     let app = Router::new()
         .fallback(fallback)
         .route("/login", get(local_ws_handler))
         .route("/devices", post(update_devices))
-        .nest_service("/", serve_dir("build".to_string()))
+        .route("/panic", post(panic))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .nest_service("/", serve_dir(String::new()))
         .route("/ws", get(user_ws_handler))
-        .nest_service("/assets", serve_dir("build/assets/".to_string()))
-        .with_state(shared_state);
+        .nest_service("/assets", serve_dir("assets/".to_string()))
+        .with_state(shared_state.clone());
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .unwrap();
+    info!("listening on {}", listener.local_addr().unwrap());
 
     axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal(shared_state))
     .await
     .unwrap();
 }
 
+async fn shutdown_signal(state: Arc<AppState>) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    // only ctrl-c elsewhere
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("shutting down");
+    let session = state.host_session.lock().await.clone();
+    let devices = state.exposed_devices.lock().await.list();
+    if let Err(e) = state.store.save(&session, &devices).await {
+        warn!("failed to persist exposed devices: {e}");
+    }
+    state.shutdown.send_replace(true);
+
+    // websockets live outside of axum's graceful shutdown, give them a moment to say goodbye
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline
+        && (state.metrics.hosts_connected.load(Ordering::SeqCst) > 0
+            || state.metrics.users_connected.load(Ordering::SeqCst) > 0)
+    {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+async fn say_goodbye(socket: &mut WebSocket) {
+    let _ = socket
+        .send(Message::Text(ServerMessage::ShuttingDown.to_json()))
+        .await;
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::AWAY,
+            reason: "server shutting down".into(),
+        })))
+        .await;
+}
+
 async fn fallback(uri: Uri) -> (StatusCode, String) {
     (StatusCode::NOT_FOUND, format!("not found: {uri}"))
 }
 
 pub fn serve_dir(web_folder: String) -> MethodRouter {
-    any_service(ServeDir::new(format!("{}/{}", "server", web_folder)))
+    any_service(ServeDir::new(format!("{}/{}", BUILD_DIR, web_folder)))
 }
 
 #[derive(Deserialize)]
 struct ConnectQuery {
     password: String,
+    session: Option<String>,
 }
 
 async fn local_ws_handler(
     ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Query(query): Query<ConnectQuery>,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
     if query.password != state.password {
+        warn!(%addr, "rejected host login: invalid password");
+        Metrics::inc(&state.metrics.rejected_logins);
         return (
             StatusCode::UNAUTHORIZED,
             Json(json!({
@@ -97,6 +199,8 @@ async fn local_ws_handler(
     {
         let already_connected = state.connected.lock().await;
         if *already_connected {
+            warn!(%addr, "rejected host login: already connected");
+            Metrics::inc(&state.metrics.rejected_logins);
             return (
                 StatusCode::CONFLICT,
                 Json(json!({
@@ -109,42 +213,95 @@ async fn local_ws_handler(
     }
 
     let state = Arc::clone(&state);
-    ws.on_upgrade(move |mut socket| async move {
-        let _ = socket.send(Message::Text(state.server_name.clone())).await;
-
-        *state.connected.lock().await = true;
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    message = state.bridge_rx.recv_async() => {
-                            if let Ok(message) = message {
-                                let _ = socket.send(message).await;
+    let session = query.session.unwrap_or_else(|| DEFAULT_SESSION.to_string());
+    let span = info_span!("host", %addr, %session);
+    ws.on_upgrade(move |mut socket| {
+        async move {
+            info!("host connected");
+            let name = ServerMessage::Name(state.server_name.clone()).to_json();
+            if let Err(e) = socket.send(Message::Text(name)).await {
+                warn!("failed to send server name: {e}");
+            }
+
+            // the host reconciles its own state with whatever was stored for its session
+            let restored = state.store.get(&session).await;
+            info!(count = restored.len(), "restoring exposed devices");
+            let restored = {
+                let mut exposed_devices = state.exposed_devices.lock().await;
+                exposed_devices.apply(DeviceUpdate::Replace(restored));
+                exposed_devices.list()
+            };
+            *state.host_session.lock().await = session;
+            let devices = ServerMessage::Devices(restored).to_json();
+            if let Err(e) = socket.send(Message::Text(devices)).await {
+                warn!("failed to send restored devices: {e}");
+            }
+
+            let mut host_messages = state.host_messages.subscribe();
+            for (id, name) in state.users.lock().await.iter() {
+                let joined = ServerMessage::UserJoined(*id, name.clone()).to_json();
+                if let Err(e) = socket.send(Message::Text(joined)).await {
+                    warn!("failed to send user: {e}");
+                }
+            }
+
+            *state.connected.lock().await = true;
+            Metrics::inc(&state.metrics.hosts_connected);
+            tokio::spawn(
+                async move {
+                    let mut flush = tokio::time::interval(state.bridge.flush_interval);
+                    let mut shutdown = state.shutdown.subscribe();
+                    loop {
+                        tokio::select! {
+                            _ = flush.tick() => {
+                                for frame in host_frames(&state, state.bridge.drain().await).await {
+                                    match socket.send(Message::Binary(frame.to_bytes())).await {
+                                        Ok(_) => Metrics::inc(&state.metrics.messages_forwarded),
+                                        Err(e) => {
+                                            warn!(?frame, "failed to forward value to host: {e}");
+                                            Metrics::inc(&state.metrics.messages_dropped);
+                                        }
+                                    }
+                                }
                             }
-                    }
-                    m = socket.recv() => {
-                        if let Some(m) = m {
-                            match m {
-                                Ok(m) => {
-                                    match m {
-                                        Message::Close(_) => {
-                                            *state.connected.lock().await = false;
-                                            state.exposed_devices.lock().await.clear();
-                                            break;
-                                        },
-                                        _ => {}
+                            _ = shutdown.changed() => {
+                                say_goodbye(&mut socket).await;
+                                break;
+                            }
+                            Ok(message) = host_messages.recv() => {
+                                if let Err(e) = socket.send(Message::Text(message.to_json())).await {
+                                    warn!(?message, "failed to send to host: {e}");
+                                }
+                            }
+                            m = socket.recv() => {
+                                match m {
+                                    Some(Ok(Message::Close(_))) => {
+                                        info!("host disconnected");
+                                        state.exposed_devices.lock().await.clear();
+                                        break;
+                                    }
+                                    Some(Ok(_)) => {}
+                                    Some(Err(e)) => {
+                                        warn!("host connection lost: {e}");
+                                        break;
+                                    }
+                                    None => {
+                                        warn!("host connection closed without close frame");
+                                        break;
                                     }
-                                },
-                                Err(_) => {
-                                    *state.connected.lock().await = false;
-                                    break;
                                 }
                             }
-
                         }
                     }
+
+                    *state.connected.lock().await = false;
+                    Metrics::dec(&state.metrics.hosts_connected);
+                    state.bridge.clear().await;
                 }
-            }
-        });
+                .in_current_span(),
+            );
+        }
+        .instrument(span)
     })
 }
 
@@ -154,6 +311,8 @@ async fn update_devices(
     Json(update): Json<DeviceUpdate>,
 ) -> impl IntoResponse {
     if query.password != state.password {
+        warn!("rejected device update: invalid password");
+        Metrics::inc(&state.metrics.rejected_logins);
         return (
             StatusCode::UNAUTHORIZED,
             Json(json!({
@@ -162,46 +321,310 @@ async fn update_devices(
             })),
         );
     }
-    let mut exposed_devices = state.exposed_devices.lock().await;
-
-    match update {
-        DeviceUpdate::Add(additions) => {
-            additions.into_iter().for_each(|a| {
-                let _ = exposed_devices.insert(a.cc, a);
-            });
-        }
-        DeviceUpdate::Remove(removals) => {
-            removals.into_iter().for_each(|r| {
-                let _ = exposed_devices.remove(&(r as u8));
-            });
+    if *state.shutdown.borrow() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "success": false,
+                "error": "Shutting down",
+            })),
+        );
+    }
+    // kept in memory while the devices are locked so updates land in order,
+    // the file is written after letting go of them
+    let exp_dev = {
+        let mut exposed_devices = state.exposed_devices.lock().await;
+        debug!(?update, "device update");
+        if let Err(e) = exposed_devices.check(&update) {
+            warn!("rejected device update: {e}");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "success": false,
+                    "error": e.to_string(),
+                })),
+            );
         }
-        DeviceUpdate::Clear => exposed_devices.clear(),
+        exposed_devices.apply(update);
+        let exp_dev = exposed_devices.list();
+        let session = state.host_session.lock().await.clone();
+        state.store.set(&session, &exp_dev).await;
+        exp_dev
     };
+    info!(count = exp_dev.len(), "exposed devices updated");
+    if let Err(e) = state.store.flush().await {
+        warn!("failed to persist exposed devices: {e}");
+    }
 
-    let mut exp_dev = exposed_devices
-        .values()
-        .cloned()
-        .collect::<Vec<Device>>()
-        .clone();
-    exp_dev.sort_by_key(|d| d.cc);
+    (StatusCode::OK, Json(json!(exp_dev)))
+}
 
-    //println!("{:?}", exp_dev);
+#[derive(Deserialize)]
+struct UserQuery {
+    name: Option<String>,
+    // see util::ClientId, users without a valid one get a fresh one per connection
+    client: Option<String>,
+}
 
-    (StatusCode::OK, Json(json!(exp_dev)))
+const MAX_NAME_LEN: usize = 32;
+
+// enough to keep generated client ids apart, they only live as long as the connection
+fn fresh_client_suffix() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+// the host already silenced its side, drop what is still queued and snap users back
+async fn panic(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ConnectQuery>,
+) -> impl IntoResponse {
+    if query.password != state.password {
+        warn!("rejected panic: invalid password");
+        Metrics::inc(&state.metrics.rejected_logins);
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "success": false,
+                "error": "Invalid password",
+            })),
+        );
+    }
+    state.bridge.clear().await;
+    let resets = state
+        .exposed_devices
+        .lock()
+        .await
+        .list()
+        .iter()
+        .filter(|d| !d.reset_outputs().is_empty())
+        .map(|d| (d.id, d.reset_position()))
+        .collect::<Vec<_>>();
+    warn!(count = resets.len(), "panic, resetting users");
+    let _ = state
+        .user_messages
+        .send(ServerMessage::Reset(resets.clone()));
+
+    (StatusCode::OK, Json(json!(resets)))
+}
+
+// a place under max_users, taken before the upgrade so concurrent connects can't
+// overshoot and given back when dropped, also when the upgrade never happens
+struct UserSlot(Arc<AppState>);
+
+impl UserSlot {
+    fn reserve(state: &Arc<AppState>) -> Option<Self> {
+        let max_users = state.limits.max_users as u64;
+        state
+            .metrics
+            .users_connected
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max_users).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| UserSlot(Arc::clone(state)))
+    }
+}
+
+impl Drop for UserSlot {
+    fn drop(&mut self) {
+        Metrics::dec(&self.0.metrics.users_connected);
+    }
 }
 
 async fn user_ws_handler(
     ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<UserQuery>,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
+    let Some(slot) = UserSlot::reserve(&state) else {
+        warn!(%addr, "rejected user: too many users");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "success": false,
+                "error": "Too many users",
+            })),
+        )
+            .into_response();
+    };
+
     let state = Arc::clone(&state);
-    ws.on_upgrade(move |mut user_socket| async {
-        tokio::spawn(async move {
-            while let Some(Ok(m)) = user_socket.recv().await {
-                if *state.connected.lock().await {
-                    let _ = state.bridge_tx.send(m);
+    ws.on_upgrade(move |mut user_socket| async move {
+        let Some(user) = state.users.lock().await.join() else {
+            warn!(%addr, "no user ids left");
+            return;
+        };
+        let name = query
+            .name
+            .map(|n| n.trim().chars().take(MAX_NAME_LEN).collect::<String>())
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| format!("user {user}"));
+        let client = query
+            .client
+            .as_deref()
+            .and_then(client_id)
+            .unwrap_or_else(|| format!("u{user}-{:x}", fresh_client_suffix()));
+        let span = info_span!("user", %addr, user, %name, %client);
+        tokio::spawn(
+            async move {
+                let _slot = slot;
+                info!("user connected");
+                state.users.lock().await.name(user, name.clone());
+                let _ = state.host_messages.send(ServerMessage::UserJoined(user, name));
+                let mut limiter = UserLimiter::new(&state.limits);
+                let mut flush = tokio::time::interval(state.limits.drain_interval);
+                let mut shutdown = state.shutdown.subscribe();
+                let mut devices = state.exposed_devices.lock().await.subscribe();
+                devices.mark_changed();
+                let mut user_messages = state.user_messages.subscribe();
+
+                loop {
+                    tokio::select! {
+                        Ok(()) = devices.changed() => {
+                            let layout = ServerMessage::Devices(devices.borrow_and_update().clone()).to_json();
+                            if let Err(e) = user_socket.send(Message::Text(layout)).await {
+                                warn!("failed to send devices: {e}");
+                                break;
+                            }
+                        }
+                        m = user_socket.recv() => {
+                            let Some(Ok(m)) = m else {
+                                break;
+                            };
+                            if !matches!(m, Message::Binary(_) | Message::Text(_)) {
+                                continue;
+                            }
+                            Metrics::inc(&state.metrics.messages_received);
+                            let Some(frame) = parse_user_message(m) else {
+                                Metrics::inc(&state.metrics.messages_dropped);
+                                continue;
+                            };
+                            let (outputs, events) = state
+                                .exposed_devices
+                                .lock()
+                                .await
+                                .get(frame.device)
+                                .filter(|d| !d.locked)
+                                .map(|d| (d.outputs(&frame.values), d.sends_events()))
+                                .unwrap_or_default();
+                            if outputs.is_empty() {
+                                Metrics::inc(&state.metrics.messages_dropped);
+                                continue;
+                            }
+                            for (slot, output) in outputs {
+                                let slot = (frame.device, slot);
+                                let admission = match events {
+                                    true => limiter.admit_event(slot, output),
+                                    false => limiter.admit(slot, output),
+                                };
+                                match admission {
+                                    Admission::Forward => forward(&state, user, &client, slot, output).await,
+                                    Admission::Deferred => debug!(?output, "rate limited, deferring"),
+                                    Admission::Coalesced => {
+                                        debug!(?output, "rate limited, coalescing");
+                                        Metrics::inc(&state.metrics.messages_dropped);
+                                    }
+                                    Admission::Overflow => {
+                                        debug!(?output, "rate limited, too many events waiting");
+                                        Metrics::inc(&state.metrics.messages_dropped);
+                                    }
+                                }
+                            }
+                        }
+                        Ok(message) = user_messages.recv() => {
+                            if let Err(e) = user_socket.send(Message::Text(message.to_json())).await {
+                                warn!("failed to send to user: {e}");
+                                break;
+                            }
+                        }
+                        _ = shutdown.changed() => {
+                            say_goodbye(&mut user_socket).await;
+                            break;
+                        }
+                        _ = flush.tick() => {
+                            for (slot, output) in limiter.drain() {
+                                forward(&state, user, &client, slot, output).await;
+                            }
+                        }
+                    }
                 }
+
+                state.bridge.leave(user).await;
+                state.users.lock().await.leave(user);
+                let _ = state.host_messages.send(ServerMessage::UserLeft(user));
+                info!("user disconnected");
             }
-        });
+            .instrument(span),
+        );
     })
+    .into_response()
+}
+
+fn parse_user_message(message: Message) -> Option<UserFrame> {
+    match message {
+        Message::Binary(data) => UserFrame::from_bytes(&data),
+        _ => None,
+    }
+}
+
+async fn forward(state: &AppState, user: UserId, client: &str, slot: Slot, frame: HostFrame) {
+    let frame = HostFrame { user, ..frame };
+    let (conflict, events) = state
+        .exposed_devices
+        .lock()
+        .await
+        .get(slot.0)
+        .map(|d| (d.conflict_policy(), d.sends_events()))
+        .unwrap_or_default();
+    if !*state.connected.lock().await {
+        Metrics::inc(&state.metrics.messages_dropped);
+        return;
+    }
+    match state
+        .bridge
+        .push(user, client, slot, frame, &conflict, events)
+        .await
+    {
+        Push::Queued => {}
+        Push::Replaced => Metrics::inc(&state.metrics.messages_dropped),
+        Push::Refused => {
+            debug!(?frame, ?conflict, "refused by conflict policy");
+            Metrics::inc(&state.metrics.messages_dropped);
+        }
+        Push::Unapproved => {
+            info!(device = slot.0, "user asks for a host approved device");
+            Metrics::inc(&state.metrics.messages_dropped);
+            let _ = state.host_messages.send(ServerMessage::Requested(
+                user,
+                client.to_string(),
+                slot.0,
+            ));
+        }
+    }
+}
+
+// devices hidden or locked since their frames were queued are dropped here
+async fn host_frames(state: &AppState, frames: Vec<HostFrame>) -> Vec<HostFrame> {
+    let exposed_devices = state.exposed_devices.lock().await;
+    frames
+        .into_iter()
+        .filter(|frame| {
+            let accepted = exposed_devices.accepts(frame.device);
+            if !accepted {
+                Metrics::inc(&state.metrics.messages_dropped);
+            }
+            accepted
+        })
+        .collect()
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(state.bridge.depth().await),
+    )
 }
@@ -0,0 +1,78 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Default)]
+pub struct Metrics {
+    pub hosts_connected: AtomicU64,
+    pub users_connected: AtomicU64,
+    pub messages_received: AtomicU64,
+    pub messages_forwarded: AtomicU64,
+    pub messages_dropped: AtomicU64,
+    pub rejected_logins: AtomicU64,
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(counter: &AtomicU64) {
+        counter.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // prometheus text exposition format
+    pub fn render(&self, bridge_queue_depth: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP midiserv_{name} {help}");
+            let _ = writeln!(out, "# TYPE midiserv_{name} {kind}");
+            let _ = writeln!(out, "midiserv_{name} {value}");
+        };
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        metric(
+            "hosts_connected",
+            "gauge",
+            "Connected hosts.",
+            get(&self.hosts_connected),
+        );
+        metric(
+            "users_connected",
+            "gauge",
+            "Connected users.",
+            get(&self.users_connected),
+        );
+        metric(
+            "messages_received_total",
+            "counter",
+            "Messages received from users.",
+            get(&self.messages_received),
+        );
+        metric(
+            "messages_forwarded_total",
+            "counter",
+            "Messages forwarded to the host.",
+            get(&self.messages_forwarded),
+        );
+        metric(
+            "messages_dropped_total",
+            "counter",
+            "Messages dropped because they were invalid, superseded, locked or had no host to go to.",
+            get(&self.messages_dropped),
+        );
+        metric(
+            "rejected_logins_total",
+            "counter",
+            "Rejected host logins and device updates.",
+            get(&self.rejected_logins),
+        );
+        metric(
+            "bridge_queue_depth",
+            "gauge",
+            "Values waiting in the bridge for the next flush.",
+            bridge_queue_depth as u64,
+        );
+
+        out
+    }
+}
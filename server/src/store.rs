@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use util::Device;
+
+// optional on-disk copy of the exposed devices per host session, enabled by setting STATE_FILE
+pub struct Store {
+    path: Option<PathBuf>,
+    sessions: Mutex<HashMap<String, Vec<Device>>>,
+    // one write at a time, each writes whatever is newest by then
+    writing: Mutex<()>,
+}
+
+impl Store {
+    pub fn from_env() -> Self {
+        Store::open(env::var("STATE_FILE").ok().map(PathBuf::from))
+    }
+
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let sessions = path
+            .as_ref()
+            .filter(|p| p.exists())
+            .and_then(|p| match fs::read_to_string(p) {
+                Ok(content) => match serde_json::from_str(&content) {
+                    Ok(sessions) => Some(sessions),
+                    Err(e) => {
+                        warn!(path = %p.display(), "ignoring unreadable state file: {e}");
+                        None
+                    }
+                },
+                Err(e) => {
+                    warn!(path = %p.display(), "failed to read state file: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        if let Some(p) = &path {
+            info!(path = %p.display(), "persisting exposed devices");
+        }
+
+        Store {
+            path,
+            sessions: Mutex::new(sessions),
+            writing: Mutex::new(()),
+        }
+    }
+
+    pub async fn get(&self, session: &str) -> Vec<Device> {
+        self.sessions
+            .lock()
+            .await
+            .get(session)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // sessions are always kept in memory, see flush for the file
+    pub async fn set(&self, session: &str, devices: &[Device]) {
+        self.sessions
+            .lock()
+            .await
+            .insert(session.to_string(), devices.to_vec());
+    }
+
+    // written next to the state file and renamed over it, so a crash mid-write
+    // leaves the previous state instead of a truncated file
+    pub async fn flush(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _writing = self.writing.lock().await;
+        let content = serde_json::to_string_pretty(&*self.sessions.lock().await)?;
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        tokio::fs::write(&temp, content).await?;
+        tokio::fs::rename(&temp, path).await
+    }
+
+    pub async fn save(&self, session: &str, devices: &[Device]) -> io::Result<()> {
+        self.set(session, devices).await;
+        self.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::UIType;
+
+    fn state_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("midiserv-{}-{name}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn slider(cc: u8) -> Device {
+        Device::new(cc, UIType::Slide, format!("cc {cc}"))
+    }
+
+    fn ccs(devices: Vec<Device>) -> Vec<u8> {
+        devices.iter().map(|d| d.cc).collect()
+    }
+
+    #[tokio::test]
+    async fn sessions_are_kept_apart() {
+        let store = Store::open(None);
+        store.set("a", &[slider(1)]).await;
+        store.set("b", &[slider(2), slider(3)]).await;
+        assert_eq!(ccs(store.get("a").await), vec![1]);
+        assert_eq!(ccs(store.get("b").await), vec![2, 3]);
+        assert!(store.get("c").await.is_empty());
+        // nothing to write without a file
+        assert!(store.flush().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn saved_sessions_come_back_after_a_restart() {
+        let path = state_file("restart");
+        let store = Store::open(Some(path.clone()));
+        store.save("a", &[slider(1)]).await.unwrap();
+        store.save("b", &[slider(2)]).await.unwrap();
+
+        let reopened = Store::open(Some(path.clone()));
+        assert_eq!(ccs(reopened.get("a").await), vec![1]);
+        assert_eq!(ccs(reopened.get("b").await), vec![2]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn flush_renames_the_temp_file_over_the_state() {
+        let path = state_file("flush");
+        let temp = path.with_extension("json.tmp");
+        fs::write(&path, "{}").unwrap();
+        let store = Store::open(Some(path.clone()));
+        store.set("a", &[slider(1)]).await;
+        // set alone only changes memory
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        store.flush().await.unwrap();
+        assert!(!temp.exists());
+        assert!(fs::read_to_string(&path).unwrap().contains("\"a\""));
+        let _ = fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn unreadable_state_starts_empty() {
+        let path = state_file("unreadable");
+        fs::write(&path, "not json").unwrap();
+        let store = Store::open(Some(path.clone()));
+        assert!(store.get("a").await.is_empty());
+        let _ = fs::remove_file(path);
+    }
+}
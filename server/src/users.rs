@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use util::UserId;
+
+// connected users by id, the host is told about them as they come and go. ids go round like
+// device ids, skipping the ones still connected, so a live id is never handed out twice
+pub struct Users {
+    names: HashMap<UserId, String>,
+    next_id: UserId,
+}
+
+impl Users {
+    pub fn new() -> Self {
+        Users {
+            names: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    // None once every id is taken
+    pub fn join(&mut self) -> Option<UserId> {
+        let id = (self.next_id..=UserId::MAX)
+            .chain(1..self.next_id)
+            .find(|id| !self.names.contains_key(id))?;
+        self.next_id = id.wrapping_add(1).max(1);
+        self.names.insert(id, String::new());
+        Some(id)
+    }
+
+    pub fn name(&mut self, id: UserId, name: String) {
+        self.names.insert(id, name);
+    }
+
+    pub fn leave(&mut self, id: UserId) {
+        self.names.remove(&id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&UserId, &String)> {
+        self.names.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_count_up_and_are_not_reused_right_away() {
+        let mut users = Users::new();
+        assert_eq!(users.join(), Some(1));
+        assert_eq!(users.join(), Some(2));
+        users.leave(1);
+        assert_eq!(users.join(), Some(3));
+    }
+
+    #[test]
+    fn wrapping_skips_live_ids() {
+        let mut users = Users::new();
+        let first = users.join().unwrap();
+        users.next_id = UserId::MAX;
+        assert_eq!(users.join(), Some(UserId::MAX));
+        // past the end it starts over at 1, which is still connected
+        assert_ne!(users.join(), Some(first));
+        assert_eq!(users.names.len(), 3);
+    }
+}
@@ -1,11 +1,20 @@
 use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
 use strum::{EnumIter, IntoEnumIterator};
+use thiserror::Error;
 
-#[derive(EnumIter, Clone, Serialize, Deserialize, Debug)]
+use crate::{client_id, ClientId, HostFrame, MidiKind};
+
+#[derive(EnumIter, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 pub enum UIType {
     Slide,
     Check,
+    Knob,
+    Button,
+    Toggle,
+    XY,
+    Selector,
+    Keys,
 }
 
 impl fmt::Display for UIType {
@@ -13,6 +22,12 @@ impl fmt::Display for UIType {
         match self {
             UIType::Slide => write!(f, "slide"),
             UIType::Check => write!(f, "check"),
+            UIType::Knob => write!(f, "knob"),
+            UIType::Button => write!(f, "button"),
+            UIType::Toggle => write!(f, "toggle"),
+            UIType::XY => write!(f, "xy"),
+            UIType::Selector => write!(f, "selector"),
+            UIType::Keys => write!(f, "keys"),
         }
     }
 }
@@ -24,6 +39,12 @@ impl FromStr for UIType {
         match s {
             "slide" => Ok(UIType::Slide),
             "check" => Ok(UIType::Check),
+            "knob" => Ok(UIType::Knob),
+            "button" => Ok(UIType::Button),
+            "toggle" => Ok(UIType::Toggle),
+            "xy" => Ok(UIType::XY),
+            "selector" => Ok(UIType::Selector),
+            "keys" => Ok(UIType::Keys),
             _ => Err(format!("'{}' is not a valid value for UIType", s)),
         }
     }
@@ -36,36 +57,1190 @@ impl UIType {
             acc
         })
     }
+
+    // xy pads default to the cc right after the device's own for y
+    pub fn default_params(&self, cc: u8) -> UIParams {
+        match self {
+            UIType::Slide | UIType::Check | UIType::Knob => UIParams::None,
+            UIType::Button => UIParams::Button { on: 127, off: 0 },
+            UIType::Toggle => UIParams::Toggle {
+                on: 127,
+                off: 0,
+                latch: true,
+            },
+            UIType::XY => UIParams::XY {
+                cc_y: cc.saturating_add(1).min(127),
+            },
+            UIType::Selector => UIParams::Selector {
+                steps: [0, 42, 85, 127]
+                    .into_iter()
+                    .map(|value| Step {
+                        label: value.to_string(),
+                        value,
+                    })
+                    .collect(),
+            },
+            UIType::Keys => UIParams::Keys {
+                base_note: 60,
+                count: 12,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Step {
+    pub label: String,
+    pub value: u8,
+}
+
+// the type specific part of a device, see UIType::default_params
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum UIParams {
+    #[default]
+    None,
+    // momentary, sends on while held and off when released
+    Button {
+        on: u8,
+        off: u8,
+    },
+    // latching toggles stay where they were put, others spring back on release
+    Toggle {
+        on: u8,
+        off: u8,
+        latch: bool,
+    },
+    // x goes out on the device cc, y on cc_y
+    XY {
+        cc_y: u8,
+    },
+    Selector {
+        steps: Vec<Step>,
+    },
+    // notes from base_note up, one per key
+    Keys {
+        base_note: u8,
+        count: u8,
+    },
+}
+
+fn byte(field: &'static str, value: u8) -> Result<(), DeviceParseError> {
+    match value {
+        0..=127 => Ok(()),
+        _ => Err(DeviceParseError::ValueOutOfRange(field, value)),
+    }
+}
+
+impl UIParams {
+    pub fn validate(&self) -> Result<(), DeviceParseError> {
+        match self {
+            UIParams::None => Ok(()),
+            UIParams::Button { on, off } | UIParams::Toggle { on, off, .. } => {
+                byte("on", *on).and(byte("off", *off))
+            }
+            UIParams::XY { cc_y } if *cc_y > 127 => {
+                Err(DeviceParseError::CcOutOfRange(cc_y.to_string()))
+            }
+            UIParams::XY { .. } => Ok(()),
+            UIParams::Selector { steps } if steps.is_empty() => Err(DeviceParseError::NoSteps),
+            UIParams::Selector { steps } => steps.iter().try_for_each(|s| byte("step", s.value)),
+            UIParams::Keys { base_note, count } => {
+                byte("note", base_note.saturating_add(count.saturating_sub(1)))
+            }
+        }
+    }
+}
+
+// the short form used by the expose form:
+// buttons "127/0", toggles "127/0 latch" or "127/0 momentary", xy pads the y cc "11",
+// selectors "low=0, mid=64, high=127" (a bare value is its own label), keys "60+12"
+impl fmt::Display for UIParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UIParams::None => Ok(()),
+            UIParams::Button { on, off } => write!(f, "{on}/{off}"),
+            UIParams::Toggle { on, off, latch } => {
+                write!(
+                    f,
+                    "{on}/{off} {}",
+                    if *latch { "latch" } else { "momentary" }
+                )
+            }
+            UIParams::XY { cc_y } => write!(f, "{cc_y}"),
+            UIParams::Selector { steps } => {
+                let steps = steps
+                    .iter()
+                    .map(|s| match s.label == s.value.to_string() {
+                        true => s.label.clone(),
+                        false => format!("{}={}", s.label, s.value),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", steps.join(", "))
+            }
+            UIParams::Keys { base_note, count } => write!(f, "{base_note}+{count}"),
+        }
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl UIParams {
+    // blank is the ui type's default
+    pub fn parse(ui_type: UIType, cc: u8, s: &str) -> Result<Self, DeviceParseError> {
+        let s = s.trim();
+        let default = ui_type.default_params(cc);
+        if s.is_empty() {
+            return Ok(default);
+        }
+        let invalid = || DeviceParseError::InvalidParams {
+            ui_type,
+            params: s.to_string(),
+            example: default.to_string(),
+        };
+        let pair = |s: &str, sep: char| {
+            s.split_once(sep)
+                .and_then(|(a, b)| Some((a.trim().parse().ok()?, b.trim().parse().ok()?)))
+                .ok_or_else(invalid)
+        };
+        match ui_type {
+            UIType::Slide | UIType::Check | UIType::Knob => Err(invalid()),
+            UIType::Button => pair(s, '/').map(|(on, off)| UIParams::Button { on, off }),
+            UIType::Toggle => {
+                let (values, mode) = s.split_once(' ').unwrap_or((s, "latch"));
+                let latch = match mode.trim() {
+                    "latch" => true,
+                    "momentary" => false,
+                    _ => return Err(invalid()),
+                };
+                pair(values, '/').map(|(on, off)| UIParams::Toggle { on, off, latch })
+            }
+            UIType::XY => s
+                .parse()
+                .map(|cc_y| UIParams::XY { cc_y })
+                .map_err(|_| invalid()),
+            UIType::Selector => s
+                .split(',')
+                .map(|step| {
+                    let (label, value) = step.split_once('=').unwrap_or((step, step));
+                    Some(Step {
+                        label: label.trim().to_string(),
+                        value: value.trim().parse().ok()?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(|steps| UIParams::Selector { steps })
+                .ok_or_else(invalid),
+            UIType::Keys => {
+                pair(s, '+').map(|(base_note, count)| UIParams::Keys { base_note, count })
+            }
+        }
+    }
+
+    pub fn fits(&self, ui_type: UIType) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(&ui_type.default_params(0))
+    }
+}
+
+#[derive(EnumIter, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum Curve {
+    #[default]
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Curve::Linear => write!(f, "linear"),
+            Curve::Exponential => write!(f, "exponential"),
+            Curve::Logarithmic => write!(f, "logarithmic"),
+        }
+    }
+}
+
+impl FromStr for Curve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" | "lin" => Ok(Curve::Linear),
+            "exponential" | "exp" => Ok(Curve::Exponential),
+            "logarithmic" | "log" => Ok(Curve::Logarithmic),
+            _ => Err(format!("'{}' is not a valid value for Curve", s)),
+        }
+    }
+}
+
+// what the server does when several users move the same device
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum Conflict {
+    #[default]
+    LastWriter,
+    // whoever moves it first keeps it until they let go for this many seconds
+    Hold(u16),
+    // of the users that moved it recently, only continuous controls
+    Average,
+    // only these clients get through, the host hears about everyone else
+    Approved(Vec<ClientId>),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::LastWriter => write!(f, "last"),
+            Conflict::Hold(secs) => write!(f, "hold {secs}"),
+            Conflict::Average => write!(f, "average"),
+            Conflict::Approved(clients) => {
+                write!(f, "approved")?;
+                clients.iter().try_for_each(|c| write!(f, " {c}"))
+            }
+        }
+    }
+}
+
+impl FromStr for Conflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let invalid = || format!("'{}' is not a valid value for Conflict", s);
+        match (words.next(), words.next()) {
+            (Some("last"), None) => Ok(Conflict::LastWriter),
+            (Some("hold"), Some(secs)) if words.next().is_none() => {
+                secs.parse().map(Conflict::Hold).map_err(|_| invalid())
+            }
+            (Some("average"), None) => Ok(Conflict::Average),
+            (Some("approved"), first) => first
+                .into_iter()
+                .chain(words)
+                .map(|c| client_id(c).ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Conflict::Approved),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Curve {
+    const STEEPNESS: f32 = 4.0;
+
+    // maps 0..=1 onto 0..=1
+    pub fn shape(&self, x: f32) -> f32 {
+        let k = Curve::STEEPNESS;
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential => (k * x).exp_m1() / k.exp_m1(),
+            Curve::Logarithmic => (k * x).ln_1p() / k.ln_1p(),
+        }
+    }
+}
+
+#[derive(EnumIter, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum Size {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Size::Small => write!(f, "small"),
+            Size::Medium => write!(f, "medium"),
+            Size::Large => write!(f, "large"),
+        }
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "small" => Ok(Size::Small),
+            "medium" => Ok(Size::Medium),
+            "large" => Ok(Size::Large),
+            _ => Err(format!("'{}' is not a valid value for Size", s)),
+        }
+    }
+}
+
+// presentation hints, users are free to ignore any of these
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Layout {
+    pub page: u8,
+    pub group: Option<String>,
+    // position within the group, ties keep the list order
+    pub order: i16,
+    // css colour, e.g. "#ff8800"
+    pub color: Option<String>,
+    pub size: Size,
+    pub icon: Option<String>,
+}
+
+// the layout fields an expose sets, the ones left as None keep what the device had
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayoutEdit {
+    pub page: Option<u8>,
+    pub group: Option<String>,
+    pub order: Option<i16>,
+    pub color: Option<String>,
+    pub size: Option<Size>,
+    pub icon: Option<String>,
+}
+
+impl LayoutEdit {
+    // everything a layout has, a missing group, colour or icon still leaves it alone
+    pub fn replacing(layout: &Layout) -> Self {
+        LayoutEdit {
+            page: Some(layout.page),
+            group: layout.group.clone(),
+            order: Some(layout.order),
+            color: layout.color.clone(),
+            size: Some(layout.size),
+            icon: layout.icon.clone(),
+        }
+    }
+
+    pub fn apply(&self, layout: &Layout) -> Layout {
+        Layout {
+            page: self.page.unwrap_or(layout.page),
+            group: self.group.clone().or_else(|| layout.group.clone()),
+            order: self.order.unwrap_or(layout.order),
+            color: self.color.clone().or_else(|| layout.color.clone()),
+            size: self.size.unwrap_or(layout.size),
+            icon: self.icon.clone().or_else(|| layout.icon.clone()),
+        }
+    }
+}
+
+// list positions sorted by page, then by group in the order groups first show up, then by order
+pub fn arrange(devices: &[Device]) -> Vec<usize> {
+    let mut groups: Vec<(u8, Option<&str>)> = vec![];
+    for d in devices {
+        let key = (d.layout.page, d.layout.group.as_deref());
+        if !groups.contains(&key) {
+            groups.push(key);
+        }
+    }
+    let mut positions = (0..devices.len()).collect::<Vec<_>>();
+    positions.sort_by_key(|&i| {
+        let layout = &devices[i].layout;
+        let group = groups
+            .iter()
+            .position(|g| *g == (layout.page, layout.group.as_deref()));
+        (layout.page, group, layout.order)
+    });
+    positions
+}
+
+// assigned by the server when a device is first exposed, 0 until then
+pub type DeviceId = u16;
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Device {
+    #[serde(default)]
+    pub id: DeviceId,
+    #[serde(default)]
+    pub channel: u8,
     pub cc: u8,
     pub ui_type: UIType,
     pub description: String,
+    // output range, values are clamped to 0..=127
+    #[serde(default)]
+    pub min: u8,
+    #[serde(default = "Device::max_value")]
+    pub max: u8,
+    // output value sent when the device is reset
+    #[serde(default)]
+    pub default: u8,
+    #[serde(default)]
+    pub invert: bool,
+    #[serde(default)]
+    pub curve: Curve,
+    #[serde(default)]
+    pub params: UIParams,
+    #[serde(default)]
+    pub layout: Layout,
+    // locked devices are shown to users but the server drops what they send
+    #[serde(default)]
+    pub locked: bool,
+    // glide towards new values at this many steps per second on the host, 0 jumps
+    #[serde(default)]
+    pub slew: u16,
+    #[serde(default)]
+    pub conflict: Conflict,
 }
 
 impl Device {
     pub fn new(cc: u8, ui_type: UIType, description: String) -> Self {
         Device {
+            id: 0,
+            channel: 0,
             cc,
             ui_type,
             description,
+            min: 0,
+            max: Device::max_value(),
+            default: 0,
+            invert: false,
+            curve: Curve::Linear,
+            params: ui_type.default_params(cc),
+            layout: Layout::default(),
+            locked: false,
+            slew: 0,
+            conflict: Conflict::LastWriter,
         }
     }
 
-    pub fn from_string_args(cc: String, ui_type: String, description: String) -> Option<Self> {
-        cc.parse::<u8>().ok().and_then(|controller| {
-            UIType::from_str(ui_type.as_str())
-                .ok()
-                .map(|ui_type| Device::new(controller, ui_type, description))
-        })
+    fn max_value() -> u8 {
+        127
+    }
+
+    // users always send 0..=127, this maps it onto what the device is allowed to output
+    pub fn scale(&self, value: u8) -> u8 {
+        let x = value.min(127) as f32 / 127.0;
+        let x = if self.invert { 1.0 - x } else { x };
+        let (min, max) = (self.min.min(127) as f32, self.max.min(127) as f32);
+        (min + self.curve.shape(x) * (max - min)).round() as u8
+    }
+
+    // what a reset sends, the default on every cc the device drives. keys have
+    // nothing to reset that all notes off doesn't already cover
+    pub fn reset_outputs(&self) -> Vec<HostFrame> {
+        let cc = |cc: u8| HostFrame::cc(self.id, self.channel, cc, self.default);
+        match &self.params {
+            UIParams::XY { cc_y } => vec![cc(self.cc), cc(*cc_y)],
+            UIParams::Keys { .. } => vec![],
+            _ => vec![cc(self.cc)],
+        }
+    }
+
+    // where a user's control goes on a reset, the position that comes out as the default
+    pub fn reset_position(&self) -> u8 {
+        let closest = |outputs: &mut dyn Iterator<Item = (u8, u8)>| {
+            outputs
+                .min_by_key(|(_, output)| output.abs_diff(self.default))
+                .map_or(0, |(position, _)| position)
+        };
+        match &self.params {
+            UIParams::Button { on, off } | UIParams::Toggle { on, off, .. } => {
+                match on.abs_diff(self.default) <= off.abs_diff(self.default) {
+                    true => 127,
+                    false => 0,
+                }
+            }
+            UIParams::Selector { steps } => {
+                closest(&mut steps.iter().enumerate().map(|(i, s)| (i as u8, s.value)))
+            }
+            _ => closest(&mut (0..=127).map(|v| (v, self.scale(v)))),
+        }
+    }
+
+    // the (channel, cc)s a slew applies to, notes and fixed values jump
+    pub fn slewed_ccs(&self) -> Vec<(u8, u8)> {
+        if self.slew == 0 {
+            return vec![];
+        }
+        match &self.params {
+            UIParams::None => vec![(self.channel, self.cc)],
+            UIParams::XY { cc_y } => vec![(self.channel, self.cc), (self.channel, *cc_y)],
+            _ => vec![],
+        }
+    }
+
+    // keys play notes, everything else sends ccs. a note and a cc with the same
+    // number on the same channel are different devices
+    pub fn kind(&self) -> MidiKind {
+        match self.ui_type {
+            UIType::Keys => MidiKind::Note,
+            _ => MidiKind::Cc,
+        }
+    }
+
+    // presses and notes are events, every one of them has to reach the host in order.
+    // everything else is a position where only the latest value counts
+    pub fn sends_events(&self) -> bool {
+        matches!(
+            self.params,
+            UIParams::Button { .. } | UIParams::Toggle { .. } | UIParams::Keys { .. }
+        )
+    }
+
+    // averaging buttons, selectors or notes makes no sense, they fall back to the last writer
+    pub fn conflict_policy(&self) -> Conflict {
+        match (&self.conflict, &self.params) {
+            (Conflict::Average, UIParams::None | UIParams::XY { .. }) => Conflict::Average,
+            (Conflict::Average, _) => Conflict::LastWriter,
+            (conflict, _) => conflict.clone(),
+        }
+    }
+
+    // turns the values of a user frame into midi, each output comes with the
+    // slot it is coalesced under so that e.g. different keys don't replace each other
+    pub fn outputs(&self, values: &[u8]) -> Vec<(u8, HostFrame)> {
+        let Some(&value) = values.first() else {
+            return vec![];
+        };
+        let cc = |cc: u8, value: u8| HostFrame::cc(self.id, self.channel, cc, value);
+
+        match &self.params {
+            UIParams::Button { on, off } | UIParams::Toggle { on, off, .. } => {
+                vec![(0, cc(self.cc, if value >= 64 { *on } else { *off }))]
+            }
+            UIParams::XY { cc_y } => {
+                let mut outputs = vec![(0, cc(self.cc, self.scale(value)))];
+                if let Some(&y) = values.get(1) {
+                    outputs.push((1, cc(*cc_y, self.scale(y))));
+                }
+                outputs
+            }
+            UIParams::Selector { steps } => steps
+                .get(value as usize)
+                .map(|step| vec![(0, cc(self.cc, step.value))])
+                .unwrap_or_default(),
+            UIParams::Keys { base_note, count } => {
+                let velocity = values.get(1).copied().unwrap_or(0).min(127);
+                if value < *count && base_note.saturating_add(value) <= 127 {
+                    let note = HostFrame::note(self.id, self.channel, base_note + value, velocity);
+                    vec![(value, note)]
+                } else {
+                    vec![]
+                }
+            }
+            UIParams::None => vec![(0, cc(self.cc, self.scale(value)))],
+        }
+    }
+
+    pub fn apply(&mut self, patch: DevicePatch) {
+        if let Some(channel) = patch.channel {
+            self.channel = channel;
+        }
+        if let Some(cc) = patch.cc {
+            self.cc = cc;
+        }
+        if let Some(ui_type) = patch.ui_type {
+            self.ui_type = ui_type;
+            if !self.params.fits(ui_type) {
+                self.params = ui_type.default_params(self.cc);
+            }
+        }
+        if let Some(description) = patch.description {
+            self.description = description;
+        }
+        if let Some(min) = patch.min {
+            self.min = min;
+        }
+        if let Some(max) = patch.max {
+            self.max = max;
+        }
+        if let Some(default) = patch.default {
+            self.default = default;
+        }
+        if let Some(invert) = patch.invert {
+            self.invert = invert;
+        }
+        if let Some(curve) = patch.curve {
+            self.curve = curve;
+        }
+        if let Some(params) = patch.params {
+            self.params = params;
+        }
+        if let Some(layout) = patch.layout {
+            self.layout = layout;
+        }
+        if let Some(locked) = patch.locked {
+            self.locked = locked;
+        }
+        if let Some(slew) = patch.slew {
+            self.slew = slew;
+        }
+        if let Some(conflict) = patch.conflict {
+            self.conflict = conflict;
+        }
+    }
+
+    // everything that ends up in a midi data byte has to fit in one, channels are 0-15
+    pub fn validate(&self) -> Result<(), DeviceParseError> {
+        if self.cc > 127 {
+            return Err(DeviceParseError::CcOutOfRange(self.cc.to_string()));
+        }
+        if self.channel > 15 {
+            return Err(DeviceParseError::ChannelOutOfRange(self.channel));
+        }
+        byte("min", self.min)?;
+        byte("max", self.max)?;
+        byte("default", self.default)?;
+        if self.min > self.max {
+            return Err(DeviceParseError::RangeReversed {
+                min: self.min,
+                max: self.max,
+            });
+        }
+        if !(self.min..=self.max).contains(&self.default) {
+            return Err(DeviceParseError::DefaultOutOfRange {
+                default: self.default,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        self.params.validate()
+    }
+
+    pub fn from_string_args(
+        cc: String,
+        ui_type: String,
+        description: String,
+    ) -> Result<Self, DeviceParseError> {
+        let cc = cc.trim();
+        let controller = match cc.parse::<u32>() {
+            Ok(controller) if controller <= 127 => controller as u8,
+            Ok(_) => return Err(DeviceParseError::CcOutOfRange(cc.to_string())),
+            Err(_) => return Err(DeviceParseError::CcNotANumber(cc.to_string())),
+        };
+        let ui_type = UIType::from_str(ui_type.trim())
+            .map_err(|_| DeviceParseError::UnknownUIType(ui_type.trim().to_string()))?;
+        Ok(Device::new(controller, ui_type, description))
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum DeviceParseError {
+    #[error("cc {0} out of range")]
+    CcOutOfRange(String),
+    #[error("cc '{0}' is not a number")]
+    CcNotANumber(String),
+    #[error("unknown ui type '{0}'")]
+    UnknownUIType(String),
+    #[error("expected {expected} columns, got {found}")]
+    ColumnCount { expected: usize, found: usize },
+    #[error("channel {0} out of range")]
+    ChannelOutOfRange(u8),
+    #[error("{0} {1} out of range")]
+    ValueOutOfRange(&'static str, u8),
+    #[error("min {min} is above max {max}, use invert to turn it around")]
+    RangeReversed { min: u8, max: u8 },
+    #[error("default {default} is outside {min}..={max}")]
+    DefaultOutOfRange { default: u8, min: u8, max: u8 },
+    #[error("unknown curve '{0}'")]
+    UnknownCurve(String),
+    #[error("unknown size '{0}', use small, medium or large")]
+    UnknownSize(String),
+    #[error("unknown conflict policy '{0}', e.g. 'last', 'hold 5', 'average' or 'approved <client> <client>'")]
+    UnknownConflict(String),
+    #[error("'{params}' are not {ui_type} params, e.g. '{example}'")]
+    InvalidParams {
+        ui_type: UIType,
+        params: String,
+        example: String,
+    },
+    #[error("a selector needs at least one step")]
+    NoSteps,
+    #[error("line {0} {1}")]
+    Line(u64, Box<DeviceParseError>),
+    // the nth device of an update or of a json or toml layout, counting from 1
+    #[error("device {0} {1}")]
+    Entry(usize, Box<DeviceParseError>),
+}
+
+impl DeviceParseError {
+    pub fn at(self, line: u64) -> Self {
+        DeviceParseError::Line(line, Box::new(self))
+    }
+
+    pub fn entry(self, n: usize) -> Self {
+        DeviceParseError::Entry(n, Box::new(self))
+    }
+}
+
+// fields left as None are kept as they are
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DevicePatch {
+    pub channel: Option<u8>,
+    pub cc: Option<u8>,
+    pub ui_type: Option<UIType>,
+    pub description: Option<String>,
+    pub min: Option<u8>,
+    pub max: Option<u8>,
+    pub default: Option<u8>,
+    pub invert: Option<bool>,
+    pub curve: Option<Curve>,
+    pub params: Option<UIParams>,
+    pub layout: Option<Layout>,
+    pub locked: Option<bool>,
+    pub slew: Option<u16>,
+    pub conflict: Option<Conflict>,
+}
+
+impl DevicePatch {
+    // what can be checked without the device it goes onto, the patched device still has
+    // to pass Device::validate
+    pub fn validate(&self) -> Result<(), DeviceParseError> {
+        if let Some(cc) = self.cc.filter(|cc| *cc > 127) {
+            return Err(DeviceParseError::CcOutOfRange(cc.to_string()));
+        }
+        if let Some(channel) = self.channel.filter(|c| *c > 15) {
+            return Err(DeviceParseError::ChannelOutOfRange(channel));
+        }
+        for (field, value) in [
+            ("min", self.min),
+            ("max", self.max),
+            ("default", self.default),
+        ] {
+            value.map_or(Ok(()), |v| byte(field, v))?;
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(DeviceParseError::RangeReversed { min, max });
+            }
+        }
+        self.params.as_ref().map_or(Ok(()), UIParams::validate)
+    }
+
+    // everything but the id, layout and lock
+    pub fn replacing(device: &Device) -> Self {
+        DevicePatch {
+            channel: Some(device.channel),
+            cc: Some(device.cc),
+            ui_type: Some(device.ui_type),
+            description: Some(device.description.clone()),
+            min: Some(device.min),
+            max: Some(device.max),
+            default: Some(device.default),
+            invert: Some(device.invert),
+            curve: Some(device.curve),
+            params: Some(device.params.clone()),
+            layout: None,
+            locked: None,
+            slew: Some(device.slew),
+            conflict: Some(device.conflict.clone()),
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DeviceUpdate {
     Add(Vec<Device>),
-    Remove(Vec<usize>),
+    Remove(Vec<DeviceId>),
+    Modify(DeviceId, DevicePatch),
+    // swaps the whole list at once
+    Replace(Vec<Device>),
+    // ids in their new order, devices left out keep their relative order after them
+    Reorder(Vec<DeviceId>),
     Clear,
 }
+
+impl DeviceUpdate {
+    // the same checks the expose form runs, a Modify only as far as the patch goes
+    pub fn validate(&self) -> Result<(), DeviceParseError> {
+        match self {
+            DeviceUpdate::Add(devices) | DeviceUpdate::Replace(devices) => devices
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, d)| d.validate().map_err(|e| e.entry(i + 1))),
+            DeviceUpdate::Modify(_, patch) => patch.validate(),
+            DeviceUpdate::Remove(_) | DeviceUpdate::Reorder(_) | DeviceUpdate::Clear => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(ui_type: UIType) -> Device {
+        Device {
+            id: 7,
+            channel: 2,
+            ..Device::new(10, ui_type, "test".to_string())
+        }
+    }
+
+    #[test]
+    fn scale_maps_onto_the_range() {
+        let d = Device {
+            min: 20,
+            max: 100,
+            ..device(UIType::Knob)
+        };
+        assert_eq!(d.scale(0), 20);
+        assert_eq!(d.scale(127), 100);
+        assert_eq!(d.scale(64), 60);
+        // users can't send more than 127, but nothing breaks if they do
+        assert_eq!(d.scale(255), 100);
+    }
+
+    #[test]
+    fn invert_reverses_the_range() {
+        let d = Device {
+            min: 20,
+            max: 100,
+            invert: true,
+            ..device(UIType::Knob)
+        };
+        assert_eq!(d.scale(0), 100);
+        assert_eq!(d.scale(127), 20);
+    }
+
+    #[test]
+    fn reversed_ranges_are_rejected_in_favour_of_invert() {
+        let d = Device {
+            min: 100,
+            max: 20,
+            default: 50,
+            ..device(UIType::Knob)
+        };
+        assert_eq!(
+            d.validate(),
+            Err(DeviceParseError::RangeReversed { min: 100, max: 20 })
+        );
+    }
+
+    #[test]
+    fn curves_keep_the_ends_and_bend_the_middle() {
+        for curve in Curve::iter() {
+            let d = Device {
+                curve,
+                ..device(UIType::Knob)
+            };
+            assert_eq!(d.scale(0), 0, "{curve}");
+            assert_eq!(d.scale(127), 127, "{curve}");
+        }
+        let at = |curve| {
+            Device {
+                curve,
+                ..device(UIType::Knob)
+            }
+            .scale(64)
+        };
+        assert!(at(Curve::Exponential) < at(Curve::Linear));
+        assert!(at(Curve::Logarithmic) > at(Curve::Linear));
+    }
+
+    #[test]
+    fn knob_outputs_one_scaled_cc() {
+        let d = Device {
+            max: 63,
+            ..device(UIType::Knob)
+        };
+        let outputs = d.outputs(&[127]);
+        assert_eq!(outputs.len(), 1);
+        let (slot, frame) = outputs[0];
+        assert_eq!(slot, 0);
+        assert_eq!(
+            (frame.device, frame.channel, frame.number, frame.value),
+            (7, 2, 10, 63)
+        );
+        assert!(d.outputs(&[]).is_empty());
+    }
+
+    #[test]
+    fn buttons_send_on_or_off() {
+        let d = device(UIType::Button);
+        assert_eq!(d.outputs(&[127])[0].1.value, 127);
+        assert_eq!(d.outputs(&[0])[0].1.value, 0);
+    }
+
+    #[test]
+    fn xy_sends_both_axes_on_their_own_slots() {
+        let d = device(UIType::XY);
+        let outputs = d.outputs(&[0, 127]);
+        let numbers = outputs
+            .iter()
+            .map(|(slot, f)| (*slot, f.number, f.value))
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![(0, 10, 0), (1, 11, 127)]);
+    }
+
+    #[test]
+    fn selector_sends_the_picked_step() {
+        let d = Device {
+            params: UIParams::Selector {
+                steps: vec![
+                    Step {
+                        label: "a".to_string(),
+                        value: 5,
+                    },
+                    Step {
+                        label: "b".to_string(),
+                        value: 90,
+                    },
+                ],
+            },
+            ..device(UIType::Selector)
+        };
+        assert_eq!(d.outputs(&[1])[0].1.value, 90);
+        assert!(d.outputs(&[2]).is_empty());
+    }
+
+    #[test]
+    fn keys_send_notes_in_range() {
+        let d = device(UIType::Keys);
+        let (slot, frame) = d.outputs(&[3, 100])[0];
+        assert_eq!(slot, 3);
+        assert_eq!(frame.kind, MidiKind::Note);
+        assert_eq!((frame.number, frame.value), (63, 100));
+        assert!(d.outputs(&[12, 100]).is_empty());
+    }
+
+    #[test]
+    fn reset_outputs_send_the_default_on_every_cc() {
+        let d = Device {
+            default: 64,
+            ..device(UIType::XY)
+        };
+        let resets = d
+            .reset_outputs()
+            .iter()
+            .map(|f| (f.number, f.value))
+            .collect::<Vec<_>>();
+        assert_eq!(resets, vec![(10, 64), (11, 64)]);
+        assert!(device(UIType::Keys).reset_outputs().is_empty());
+        assert_eq!(device(UIType::Knob).reset_outputs().len(), 1);
+    }
+
+    #[test]
+    fn params_round_trip_through_text() {
+        for ui_type in UIType::iter() {
+            let params = ui_type.default_params(10);
+            let text = params.to_string();
+            assert_eq!(UIParams::parse(ui_type, 10, &text), Ok(params), "{ui_type}");
+        }
+        assert_eq!(
+            UIParams::parse(UIType::Toggle, 1, "100/20 momentary"),
+            Ok(UIParams::Toggle {
+                on: 100,
+                off: 20,
+                latch: false
+            })
+        );
+        let steps = UIParams::parse(UIType::Selector, 1, "low=0, 64").unwrap();
+        assert_eq!(steps.to_string(), "low=0, 64");
+        assert!(UIParams::parse(UIType::Keys, 1, "60").is_err());
+        assert!(UIParams::parse(UIType::Knob, 1, "1").is_err());
+    }
+
+    #[test]
+    fn conflicts_round_trip_through_text() {
+        let approved = Conflict::Approved(vec!["a1-b2".to_string(), "c_3".to_string()]);
+        for conflict in [
+            Conflict::LastWriter,
+            Conflict::Hold(5),
+            Conflict::Average,
+            Conflict::Approved(vec![]),
+            approved,
+        ] {
+            let text = conflict.to_string();
+            assert_eq!(text.parse::<Conflict>(), Ok(conflict), "{text}");
+        }
+        assert_eq!(
+            "approved a1-b2 c_3"
+                .parse::<Conflict>()
+                .unwrap()
+                .to_string(),
+            "approved a1-b2 c_3"
+        );
+        for bad in [
+            "",
+            "first",
+            "hold",
+            "hold x",
+            "hold 5 6",
+            "average 2",
+            "approved a/b",
+        ] {
+            assert!(bad.parse::<Conflict>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn presses_and_notes_are_events() {
+        assert!(device(UIType::Button).sends_events());
+        assert!(device(UIType::Toggle).sends_events());
+        assert!(device(UIType::Keys).sends_events());
+        assert!(!device(UIType::Knob).sends_events());
+        assert!(!device(UIType::Selector).sends_events());
+    }
+
+    #[test]
+    fn layout_edits_only_touch_what_they_name() {
+        let layout = Layout {
+            page: 1,
+            group: Some("mixer".to_string()),
+            icon: Some("fader".to_string()),
+            ..Default::default()
+        };
+        let edit = LayoutEdit {
+            order: Some(-2),
+            size: Some(Size::Small),
+            ..Default::default()
+        };
+        let edited = edit.apply(&layout);
+        assert_eq!(
+            (edited.page, edited.order, edited.size),
+            (1, -2, Size::Small)
+        );
+        assert_eq!(edited.group, layout.group);
+        assert_eq!(edited.icon, layout.icon);
+        assert_eq!(
+            LayoutEdit::replacing(&edited).apply(&Layout::default()),
+            edited
+        );
+        for size in Size::iter() {
+            assert_eq!(size.to_string().parse::<Size>(), Ok(size));
+        }
+    }
+
+    #[test]
+    fn only_keys_play_notes() {
+        assert_eq!(device(UIType::Keys).kind(), MidiKind::Note);
+        assert_eq!(device(UIType::Knob).kind(), MidiKind::Cc);
+        assert_eq!(device(UIType::Button).kind(), MidiKind::Cc);
+    }
+
+    #[test]
+    fn validate_checks_bytes_and_the_default() {
+        assert_eq!(device(UIType::Knob).validate(), Ok(()));
+        let d = Device {
+            channel: 16,
+            ..device(UIType::Knob)
+        };
+        assert_eq!(d.validate(), Err(DeviceParseError::ChannelOutOfRange(16)));
+        let d = Device {
+            min: 10,
+            max: 20,
+            default: 30,
+            ..device(UIType::Knob)
+        };
+        assert_eq!(
+            d.validate(),
+            Err(DeviceParseError::DefaultOutOfRange {
+                default: 30,
+                min: 10,
+                max: 20
+            })
+        );
+        let d = Device {
+            params: UIParams::Keys {
+                base_note: 120,
+                count: 12,
+            },
+            ..device(UIType::Keys)
+        };
+        assert_eq!(
+            d.validate(),
+            Err(DeviceParseError::ValueOutOfRange("note", 131))
+        );
+    }
+
+    #[test]
+    fn selectors_need_steps() {
+        assert_eq!(device(UIType::Selector).validate(), Ok(()));
+        let d = Device {
+            params: UIParams::Selector { steps: vec![] },
+            ..device(UIType::Selector)
+        };
+        assert_eq!(d.validate(), Err(DeviceParseError::NoSteps));
+    }
+
+    fn placed(page: u8, group: Option<&str>, order: i16) -> Device {
+        Device {
+            layout: Layout {
+                page,
+                group: group.map(str::to_string),
+                order,
+                ..Layout::default()
+            },
+            ..device(UIType::Knob)
+        }
+    }
+
+    #[test]
+    fn arrange_sorts_by_page_then_group_then_order() {
+        let devices = vec![
+            placed(1, None, 0),
+            placed(0, Some("drums"), 2),
+            placed(0, Some("bass"), 0),
+            placed(0, Some("drums"), 1),
+            placed(0, None, 0),
+        ];
+        // groups keep the order they first show up in, not alphabetical
+        assert_eq!(arrange(&devices), vec![3, 1, 2, 4, 0]);
+    }
+
+    #[test]
+    fn arrange_keeps_list_order_on_ties() {
+        let devices = vec![placed(0, None, 0), placed(0, None, 0), placed(0, None, -1)];
+        assert_eq!(arrange(&devices), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn patches_are_checked_on_their_own() {
+        assert_eq!(DevicePatch::default().validate(), Ok(()));
+        let patch = DevicePatch {
+            min: Some(90),
+            max: Some(20),
+            ..DevicePatch::default()
+        };
+        assert_eq!(
+            patch.validate(),
+            Err(DeviceParseError::RangeReversed { min: 90, max: 20 })
+        );
+        let patch = DevicePatch {
+            default: Some(200),
+            ..DevicePatch::default()
+        };
+        assert_eq!(
+            patch.validate(),
+            Err(DeviceParseError::ValueOutOfRange("default", 200))
+        );
+        let update = DeviceUpdate::Add(vec![
+            device(UIType::Knob),
+            Device {
+                channel: 16,
+                ..device(UIType::Knob)
+            },
+        ]);
+        assert_eq!(
+            update.validate(),
+            Err(DeviceParseError::ChannelOutOfRange(16).entry(2))
+        );
+    }
+
+    #[test]
+    fn reset_positions_come_out_as_the_default() {
+        let d = Device {
+            min: 20,
+            max: 100,
+            default: 40,
+            invert: true,
+            curve: Curve::Exponential,
+            ..device(UIType::Knob)
+        };
+        let position = d.reset_position();
+        assert!(d.scale(position).abs_diff(40) <= 1, "{position}");
+        let upright = Device {
+            invert: false,
+            ..d.clone()
+        }
+        .reset_position();
+        assert!(
+            (127 - position).abs_diff(upright) <= 1,
+            "{position} {upright}"
+        );
+
+        let d = Device {
+            default: 127,
+            ..device(UIType::Button)
+        };
+        assert_eq!(d.reset_position(), 127);
+        assert_eq!(device(UIType::Toggle).reset_position(), 0);
+
+        let d = Device {
+            default: 85,
+            ..device(UIType::Selector)
+        };
+        assert_eq!(d.reset_position(), 2);
+    }
+}
@@ -1,21 +1,58 @@
 mod exposed_devices;
 use clipboard::{ClipboardContext, ClipboardProvider};
-pub use exposed_devices::{Device, DeviceUpdate, UIType};
+pub use exposed_devices::{
+    arrange, Conflict, Curve, Device, DeviceId, DeviceParseError, DevicePatch, DeviceUpdate,
+    Layout, LayoutEdit, Size, Step, UIParams, UIType,
+};
+use std::path::PathBuf;
 mod midi;
-pub use midi::{Midi, MidiCmd};
+pub use midi::{learn, Learned, Midi, MidiCmd};
+mod protocol;
+pub use protocol::{client_id, ClientId, HostFrame, MidiKind, ServerMessage, UserFrame, UserId};
 
 #[derive(Clone, Debug)]
 pub struct Login {
     pub url: String,
     pub pass: String,
+    // the server keeps a layout per session, None is its default one
+    pub session: Option<String>,
 }
 
+// only ever sent through channels one at a time, boxing the big ones buys nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum DeviceCmd {
     Login(Login),
     CopyToClipboard,
     Update(DeviceUpdate),
+    // a new device, or the patch for the one already on its channel and cc, the layout
+    // edit goes onto either
+    Expose(Device, DevicePatch, LayoutEdit),
+    // positions in the currently exposed list
+    Hide(Vec<usize>),
+    Move(usize, isize),
+    // also a position, flips the device's lock
+    ToggleLock(usize),
     Paste,
+    Reconcile(Vec<Device>),
+    // layout files, the format goes by extension
+    Save(PathBuf),
+    Open(PathBuf),
+    // named layouts kept by the local app
+    SavePreset(String),
+    LoadPreset(String),
+    DeletePreset(String),
+    // community cc map and the instrument to take from it
+    ImportCcMap(PathBuf, String),
+    // edits of the device list, re-synced to the server as a Replace
+    Undo,
+    Redo,
+    // silence everything and put devices back to their defaults
+    Panic,
+    // the connection dropped, forget the list without touching the server's copy
+    Disconnected,
+    // lets a client through on a host approved device
+    Approve(DeviceId, ClientId),
 }
 
 pub fn get_clipboard_content() -> Option<String> {
@@ -1,18 +1,43 @@
-use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
+use midir::{MidiInput, MidiOutput, MidiOutputConnection, MidiOutputPort};
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing::{debug, info, trace, trace_span, warn};
+
+use crate::MidiKind;
 
 // inspired by https://github.com/Boddlnagg/midir/blob/master/examples/test_play.rs
 
 const CC_MESSAGE: u8 = 0xB0;
+const NOTE_ON_MESSAGE: u8 = 0x90;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
 
 pub struct Midi {
     conn: Option<MidiOutputConnection>,
     ports: Vec<Port>,
+    // a moving knob without a port would otherwise warn for every value
+    warned: bool,
 }
 
 pub enum MidiCmd {
     Dummy(u8),
-    Signal(u8, u8),
+    Signal(u8, u8, u8),
+    // velocity 0 is a note off
+    Note(u8, u8, u8),
     Port(usize),
+    // wait for the next cc/note on any input
+    Learn,
+    // all notes off and reset all controllers on these channels
+    Panic(Vec<u8>),
+    // steps per second by (channel, cc), replaces what was there
+    Slew(Vec<((u8, u8), u16)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Learned {
+    pub kind: MidiKind,
+    pub channel: u8,
+    pub number: u8,
 }
 
 struct Port {
@@ -33,6 +58,7 @@ impl Midi {
             .ok()
             .map(|midi_output| Midi {
                 conn: None,
+                warned: false,
                 ports: midi_output
                     .ports()
                     .into_iter()
@@ -45,7 +71,15 @@ impl Midi {
                     })
                     .collect(),
             })
-            .unwrap()
+            // e.g. no sequencer on a headless box, keep running without outputs
+            .unwrap_or_else(|| {
+                warn!("no midi output available");
+                Midi {
+                    conn: None,
+                    ports: vec![],
+                    warned: false,
+                }
+            })
     }
 
     pub fn get_ports(&mut self) -> Vec<String> {
@@ -55,18 +89,129 @@ impl Midi {
             .collect()
     }
 
+    // an index or part of a port's "id|name"
+    pub fn find_port(&mut self, port: &str) -> Option<usize> {
+        let ports = self.get_ports();
+        port.parse::<usize>()
+            .ok()
+            .filter(|i| *i < ports.len())
+            .or_else(|| ports.iter().position(|p| p.contains(port)))
+    }
+
     pub fn update_port(&mut self, out_port: usize) {
+        self.warned = false;
         self.conn = self.ports.get(out_port).and_then(|p| {
             MidiOutput::new("midiserve")
                 .ok()
-                .and_then(|m| m.connect(&p.port, "midiserv").ok())
+                .and_then(|m| match m.connect(&p.port, "midiserv") {
+                    Ok(conn) => {
+                        info!(port = %p.name, "midi port connected");
+                        Some(conn)
+                    }
+                    Err(e) => {
+                        warn!(port = %p.name, "failed to connect midi port: {e}");
+                        None
+                    }
+                })
         });
     }
 
-    pub fn send_cc(&mut self, controller: u8, value: u8) {
-        self.conn
-            .as_mut()
-            .and_then(|c| c.send(&[CC_MESSAGE, controller, value]).ok());
+    pub fn send_cc(&mut self, channel: u8, controller: u8, value: u8) {
+        let _span = trace_span!("send_cc", channel, controller, value).entered();
+        self.send(&[CC_MESSAGE | (channel & 0x0F), controller, value]);
+    }
+
+    pub fn panic(&mut self, channels: &[u8]) {
+        for &channel in channels {
+            self.send_cc(channel, ALL_NOTES_OFF, 0);
+            self.send_cc(channel, RESET_ALL_CONTROLLERS, 0);
+        }
+    }
+
+    pub fn send_note(&mut self, channel: u8, note: u8, velocity: u8) {
+        let _span = trace_span!("send_note", channel, note, velocity).entered();
+        self.send(&[NOTE_ON_MESSAGE | (channel & 0x0F), note, velocity]);
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        match self.conn.as_mut() {
+            Some(c) => match c.send(message) {
+                Ok(()) => trace!("sent"),
+                Err(e) => warn!("failed to send: {e}"),
+            },
+            None if !self.warned => {
+                warn!("no midi port selected, dropping values until one is");
+                self.warned = true;
+            }
+            None => debug!("no midi port selected"),
+        }
+    }
+}
+
+// listens on every input port at once and returns the first cc or note on,
+// the connections are closed again once something came in or time ran out
+pub fn learn(timeout: Duration) -> Option<Learned> {
+    let (tx, rx) = mpsc::channel();
+    let ports = MidiInput::new("midiserve")
+        .map(|m| m.ports())
+        .unwrap_or_default();
+    let connections = ports
+        .iter()
+        .filter_map(|port| {
+            let input = MidiInput::new("midiserve").ok()?;
+            let name = input.port_name(port).unwrap_or_default();
+            let tx = tx.clone();
+            match input.connect(
+                port,
+                "midiserv-learn",
+                move |_, message, _| {
+                    if let Some(learned) = parse_learned(message) {
+                        let _ = tx.send(learned);
+                    }
+                },
+                (),
+            ) {
+                Ok(conn) => {
+                    debug!(port = %name, "listening for midi learn");
+                    Some(conn)
+                }
+                Err(e) => {
+                    warn!(port = %name, "failed to listen on midi input: {e}");
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if connections.is_empty() {
+        warn!("no midi inputs to learn from");
+        return None;
+    }
+    let learned = rx.recv_timeout(timeout).ok();
+    drop(connections);
+    learned
+}
+
+fn parse_learned(message: &[u8]) -> Option<Learned> {
+    let (&status, &number, &value) = (message.first()?, message.get(1)?, message.get(2)?);
+    // data bytes never have the top bit set, anything else is not a whole message
+    if number > 0x7F || value > 0x7F {
+        return None;
+    }
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        CC_MESSAGE => Some(Learned {
+            kind: MidiKind::Cc,
+            channel,
+            number,
+        }),
+        // a note on with velocity 0 is the release of one
+        NOTE_ON_MESSAGE if value > 0 => Some(Learned {
+            kind: MidiKind::Note,
+            channel,
+            number,
+        }),
+        _ => None,
     }
 }
 
@@ -75,3 +220,46 @@ impl Default for Midi {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn learned(kind: MidiKind, channel: u8, number: u8) -> Option<Learned> {
+        Some(Learned {
+            kind,
+            channel,
+            number,
+        })
+    }
+
+    #[test]
+    fn ccs_and_notes_are_learned_with_their_channel() {
+        assert_eq!(parse_learned(&[0xB0, 7, 100]), learned(MidiKind::Cc, 0, 7));
+        assert_eq!(parse_learned(&[0xBF, 74, 0]), learned(MidiKind::Cc, 15, 74));
+        assert_eq!(
+            parse_learned(&[0x93, 60, 90]),
+            learned(MidiKind::Note, 3, 60)
+        );
+    }
+
+    #[test]
+    fn releases_and_other_messages_are_skipped() {
+        // note on with velocity 0, note off, pitch bend, program change
+        assert_eq!(parse_learned(&[0x90, 60, 0]), None);
+        assert_eq!(parse_learned(&[0x80, 60, 64]), None);
+        assert_eq!(parse_learned(&[0xE0, 0, 64]), None);
+        assert_eq!(parse_learned(&[0xC0, 5]), None);
+    }
+
+    #[test]
+    fn partial_and_malformed_messages_are_skipped() {
+        assert_eq!(parse_learned(&[]), None);
+        assert_eq!(parse_learned(&[0xB0]), None);
+        assert_eq!(parse_learned(&[0xB0, 7]), None);
+        // running status, the data bytes arrive without a status byte
+        assert_eq!(parse_learned(&[7, 100, 8]), None);
+        assert_eq!(parse_learned(&[0xB0, 0x87, 100]), None);
+        assert_eq!(parse_learned(&[0x90, 60, 0xF8]), None);
+    }
+}
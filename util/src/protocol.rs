@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Device, DeviceId};
+
+// text frames the server sends down the host and user websockets
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    Name(String),
+    Devices(Vec<Device>),
+    ShuttingDown,
+    // only sent to the host so it can tell who is playing
+    UserJoined(UserId, String),
+    UserLeft(UserId),
+    // after a panic on the host, controls snap back to these positions, the ones that
+    // come out as each device's default
+    Reset(Vec<(DeviceId, u8)>),
+    // host only, a user moved a host approved device they aren't approved for
+    Requested(UserId, ClientId, DeviceId),
+}
+
+// the server's session number of a user
+pub type UserId = u16;
+
+// picked by the frontend and kept across reloads, what approvals go by
+pub type ClientId = String;
+
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+// letters, digits, '-' and '_' only, so lists of them survive being written out as words
+pub fn client_id(s: &str) -> Option<ClientId> {
+    let s = s.trim();
+    let valid = (1..=MAX_CLIENT_ID_LEN).contains(&s.len())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| s.to_string())
+}
+
+impl ServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn from_json(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+// binary frame from a user: [id_hi, id_lo, value, ...], most controls send a
+// single value, xy pads send x and y, keys send the key index and velocity
+#[derive(Debug, Clone)]
+pub struct UserFrame {
+    pub device: DeviceId,
+    pub values: Vec<u8>,
+}
+
+impl UserFrame {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let [hi, lo] = self.device.to_be_bytes();
+        [vec![hi, lo], self.values.clone()].concat()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let values = data.get(2..).filter(|v| !v.is_empty())?.to_vec();
+        Some(UserFrame {
+            device: DeviceId::from_be_bytes([*data.first()?, *data.get(1)?]),
+            values,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiKind {
+    Cc,
+    Note,
+}
+
+// binary frame the server forwards to the host:
+// [kind, channel, number, value, id_hi, id_lo, user_hi, user_lo]
+#[derive(Debug, Clone, Copy)]
+pub struct HostFrame {
+    pub device: DeviceId,
+    pub user: UserId,
+    pub kind: MidiKind,
+    pub channel: u8,
+    pub number: u8,
+    pub value: u8,
+}
+
+impl HostFrame {
+    pub fn cc(device: DeviceId, channel: u8, cc: u8, value: u8) -> Self {
+        HostFrame {
+            device,
+            user: 0,
+            kind: MidiKind::Cc,
+            channel,
+            number: cc,
+            value,
+        }
+    }
+
+    pub fn note(device: DeviceId, channel: u8, note: u8, velocity: u8) -> Self {
+        HostFrame {
+            device,
+            user: 0,
+            kind: MidiKind::Note,
+            channel,
+            number: note,
+            value: velocity,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let [hi, lo] = self.device.to_be_bytes();
+        let [user_hi, user_lo] = self.user.to_be_bytes();
+        let kind = match self.kind {
+            MidiKind::Cc => 0,
+            MidiKind::Note => 1,
+        };
+        vec![
+            kind,
+            self.channel,
+            self.number,
+            self.value,
+            hi,
+            lo,
+            user_hi,
+            user_lo,
+        ]
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        Some(HostFrame {
+            kind: match data.first()? {
+                0 => MidiKind::Cc,
+                1 => MidiKind::Note,
+                _ => return None,
+            },
+            channel: *data.get(1)?,
+            number: *data.get(2)?,
+            value: *data.get(3)?,
+            device: DeviceId::from_be_bytes([*data.get(4)?, *data.get(5)?]),
+            // servers from before users were tagged leave it out
+            user: data
+                .get(6..8)
+                .map(|u| UserId::from_be_bytes([u[0], u[1]]))
+                .unwrap_or(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_frames_round_trip() {
+        let frame = UserFrame {
+            device: 0x1234,
+            values: vec![10, 20],
+        };
+        assert_eq!(frame.to_bytes(), vec![0x12, 0x34, 10, 20]);
+        let parsed = UserFrame::from_bytes(&frame.to_bytes()).unwrap();
+        assert_eq!((parsed.device, parsed.values), (0x1234, vec![10, 20]));
+    }
+
+    #[test]
+    fn user_frames_need_an_id_and_a_value() {
+        assert!(UserFrame::from_bytes(&[]).is_none());
+        assert!(UserFrame::from_bytes(&[0]).is_none());
+        assert!(UserFrame::from_bytes(&[0, 1]).is_none());
+        assert!(UserFrame::from_bytes(&[0, 1, 64]).is_some());
+    }
+
+    #[test]
+    fn host_frames_round_trip() {
+        let frame = HostFrame {
+            user: 0x0102,
+            ..HostFrame::note(0x0304, 9, 60, 100)
+        };
+        let bytes = frame.to_bytes();
+        assert_eq!(bytes, vec![1, 9, 60, 100, 3, 4, 1, 2]);
+        let parsed = HostFrame::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.kind, MidiKind::Note);
+        assert_eq!((parsed.channel, parsed.number, parsed.value), (9, 60, 100));
+        assert_eq!((parsed.device, parsed.user), (0x0304, 0x0102));
+        let cc = HostFrame::from_bytes(&HostFrame::cc(1, 0, 7, 64).to_bytes()).unwrap();
+        assert_eq!(cc.kind, MidiKind::Cc);
+    }
+
+    #[test]
+    fn host_frames_from_older_servers_have_no_user() {
+        let parsed = HostFrame::from_bytes(&[0, 2, 7, 64, 0, 5]).unwrap();
+        assert_eq!((parsed.device, parsed.user, parsed.value), (5, 0, 64));
+        // a half sent user is left out too
+        assert_eq!(
+            HostFrame::from_bytes(&[0, 2, 7, 64, 0, 5, 1]).unwrap().user,
+            0
+        );
+    }
+
+    #[test]
+    fn short_or_unknown_host_frames_are_dropped() {
+        assert!(HostFrame::from_bytes(&[]).is_none());
+        assert!(HostFrame::from_bytes(&[0, 2, 7, 64, 0]).is_none());
+        assert!(HostFrame::from_bytes(&[2, 2, 7, 64, 0, 5]).is_none());
+    }
+
+    #[test]
+    fn client_ids_are_short_words() {
+        assert_eq!(client_id(" ab-1_C "), Some("ab-1_C".to_string()));
+        assert_eq!(client_id(&"a".repeat(64)).map(|c| c.len()), Some(64));
+        assert_eq!(client_id(&"a".repeat(65)), None);
+        assert_eq!(client_id(""), None);
+        assert_eq!(client_id("a b"), None);
+        assert_eq!(client_id("a/b"), None);
+        assert_eq!(client_id("é"), None);
+    }
+
+    #[test]
+    fn server_messages_round_trip_as_json() {
+        let message = ServerMessage::Requested(3, "abc".to_string(), 7);
+        assert_eq!(message.to_json(), r#"{"Requested":[3,"abc",7]}"#);
+        assert!(matches!(
+            ServerMessage::from_json(&message.to_json()),
+            Some(ServerMessage::Requested(3, c, 7)) if c == "abc"
+        ));
+        assert!(ServerMessage::from_json("\"name\"").is_none());
+    }
+}